@@ -1,4 +1,7 @@
-use clap::Arg;
+use clap::{Arg, ArgAction};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
 use std::os::unix::process::CommandExt;
 use std::process;
 
@@ -13,29 +16,255 @@ macro_rules! die {
     });
 }
 
-fn make_command(name: &str, args: Vec<&str>) -> process::Command {
+fn make_command(name: &OsStr, args: Vec<&OsString>) -> process::Command {
     let mut command = process::Command::new(name);
 
     for arg in args {
         command.arg(arg);
     }
 
-    return command;
+    command
+}
+
+fn check(file: Option<&String>, reference: &str) {
+    let fail =
+        |path: &str, e: dotenvy::Error| -> ! { die!("error: failed to load {}: {}", path, e) };
+    // only the keys of the reference matter
+    let reference = dotenvy::from_filename_iter(reference)
+        .and_then(|iter| iter.no_substitution().into_map())
+        .unwrap_or_else(|e| fail(reference, e));
+    let dotenv = match file {
+        Some(file) => dotenvy::from_filename_map(file).unwrap_or_else(|e| fail(file, e)),
+        None => match dotenvy::dotenv_map() {
+            Ok(map) => map,
+            Err(e) if e.not_found() => dotenvy::EnvMap::new(),
+            Err(e) => die!("error: failed to load .env: {}", e),
+        },
+    };
+
+    match dotenvy::check(&reference, &dotenv) {
+        Ok(()) => {}
+        Err(dotenvy::Error::Check(diff)) => {
+            for key in diff.missing() {
+                eprintln!("missing: {}", key);
+            }
+            for key in diff.empty() {
+                eprintln!("empty: {}", key);
+            }
+            for key in diff.undeclared() {
+                eprintln!("undeclared: {}", key);
+            }
+            process::exit(1);
+        }
+        Err(e) => die!("error: {}", e),
+    }
+}
+
+fn fmt(files: Vec<&str>, check: bool, sort: bool) {
+    let mut unformatted = false;
+    for file in files {
+        let mut document = dotenvy::Document::from_path(file)
+            .unwrap_or_else(|e| die!("error: failed to parse {}: {}", file, e));
+        let original = document.to_string();
+        document.format();
+        if sort {
+            document.sort_groups();
+        }
+        if document.to_string() == original {
+            continue;
+        }
+
+        if check {
+            eprintln!("not formatted: {}", file);
+            unformatted = true;
+        } else {
+            document
+                .save(file)
+                .unwrap_or_else(|e| die!("error: failed to write {}: {}", file, e));
+        }
+    }
+    if unformatted {
+        process::exit(1);
+    }
+}
+
+fn lint(files: Vec<&str>, json: bool) {
+    let mut diagnostics = Vec::new();
+    for file in files {
+        let text = fs::read_to_string(file)
+            .unwrap_or_else(|e| die!("error: failed to read {}: {}", file, e));
+        diagnostics.extend(
+            dotenvy::lint(&text)
+                .into_iter()
+                .map(|diagnostic| (file, diagnostic)),
+        );
+    }
+
+    if json {
+        let objects: Vec<String> = diagnostics
+            .iter()
+            .map(|(file, diagnostic)| {
+                format!(
+                    "{{\"file\":{},\"line\":{},\"rule\":\"{}\",\"severity\":\"{}\",\"message\":{}}}",
+                    json_string(file),
+                    diagnostic.line(),
+                    diagnostic.rule(),
+                    diagnostic.severity(),
+                    json_string(diagnostic.message())
+                )
+            })
+            .collect();
+        println!("[{}]", objects.join(","));
+    } else {
+        for (file, diagnostic) in &diagnostics {
+            println!("{}:{}: {}", file, diagnostic.line(), diagnostic);
+        }
+    }
+    if !diagnostics.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns the files given to a subcommand, or else the one given with `--file`
+/// or `.env`.
+fn files<'a>(matches: &'a clap::ArgMatches, subcommand: &'a clap::ArgMatches) -> Vec<&'a str> {
+    match subcommand.get_many::<String>("FILES") {
+        Some(files) => files.map(String::as_str).collect(),
+        None => vec![matches
+            .get_one::<String>("FILE")
+            .map_or(".env", String::as_str)],
+    }
+}
+
+/// Splits `args` at a `--` given before the command, returning the command
+/// after it, which is run even if it is named like a subcommand.
+fn split_escaped(mut args: Vec<OsString>) -> (Vec<OsString>, Option<Vec<OsString>>) {
+    let mut index = 1;
+    while index < args.len() {
+        match args[index].to_str() {
+            Some("--") => {
+                let command = args.split_off(index + 1);
+                args.pop();
+                return (args, Some(command));
+            }
+            Some("-f") | Some("--file") => index += 2,
+            Some(arg) if arg.starts_with('-') => index += 1,
+            _ => break,
+        }
+    }
+    (args, None)
 }
 
 fn main() {
+    let (args, escaped) = split_escaped(env::args_os().collect());
     let matches = clap::Command::new("dotenvy")
         .about("Run a command using the environment in a .env file")
-        .override_usage("dotenvy <COMMAND> [ARGS]...")
+        .override_usage(
+            "dotenvy [OPTIONS] <COMMAND> [ARGS]...\n       \
+             dotenvy [OPTIONS] -- <COMMAND> [ARGS]...\n       \
+             dotenvy [OPTIONS] check [--example <FILE>]\n       \
+             dotenvy [OPTIONS] fmt [--check] [--sort] [FILES]...\n       \
+             dotenvy [OPTIONS] lint [--format <FORMAT>] [FILES]...",
+        )
         .allow_external_subcommands(true)
-        .arg_required_else_help(true)
+        .disable_help_subcommand(true)
+        .after_help("Commands named like a subcommand, such as `fmt`, can be run after `--`.")
+        .arg_required_else_help(escaped.is_none())
         .arg(
             Arg::new("FILE")
                 .short('f')
                 .long("file")
                 .help("Use a specific .env file (defaults to .env)"),
         )
-        .get_matches();
+        .subcommand(
+            clap::Command::new("check")
+                .about("Check the .env file and environment against a reference file")
+                .arg(
+                    Arg::new("EXAMPLE")
+                        .long("example")
+                        .default_value(".env.example")
+                        .help("The reference file declaring the expected variables"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("fmt")
+                .about("Format .env files without changing the values they define")
+                .arg(
+                    Arg::new("CHECK")
+                        .long("check")
+                        .action(ArgAction::SetTrue)
+                        .help("List the files that are not formatted instead of writing them"),
+                )
+                .arg(
+                    Arg::new("SORT")
+                        .long("sort")
+                        .action(ArgAction::SetTrue)
+                        .help("Sort the keys between blank or comment lines"),
+                )
+                .arg(
+                    Arg::new("FILES")
+                        .num_args(0..)
+                        .help("The files to format (defaults to the .env file)"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("lint")
+                .about("Check .env files for likely mistakes")
+                .arg(
+                    Arg::new("FORMAT")
+                        .long("format")
+                        .value_parser(["text", "json"])
+                        .default_value("text")
+                        .help("How to print the problems found"),
+                )
+                .arg(
+                    Arg::new("FILES")
+                        .num_args(0..)
+                        .help("The files to check (defaults to the .env file)"),
+                ),
+        )
+        .get_matches_from(args);
+
+    if let Some(("check", check_matches)) = matches.subcommand() {
+        let reference = check_matches
+            .get_one::<String>("EXAMPLE")
+            .expect("has a default value");
+        check(matches.get_one::<String>("FILE"), reference);
+        return;
+    }
+
+    if let Some(("fmt", fmt_matches)) = matches.subcommand() {
+        let files = files(&matches, fmt_matches);
+        fmt(
+            files,
+            fmt_matches.get_flag("CHECK"),
+            fmt_matches.get_flag("SORT"),
+        );
+        return;
+    }
+
+    if let Some(("lint", lint_matches)) = matches.subcommand() {
+        let json = lint_matches
+            .get_one::<String>("FORMAT")
+            .map_or(false, |format| format == "json");
+        lint(files(&matches, lint_matches), json);
+        return;
+    }
 
     match matches.get_one::<String>("FILE") {
         None => dotenvy::dotenv(),
@@ -43,16 +272,19 @@ fn main() {
     }
     .unwrap_or_else(|e| die!("error: failed to load environment: {}", e));
 
-    let mut command = match matches.subcommand() {
-        Some((name, matches)) => {
+    let mut command = match (escaped, matches.subcommand()) {
+        (Some(escaped), _) if !escaped.is_empty() => {
+            make_command(&escaped[0], escaped[1..].iter().collect())
+        }
+        (None, Some((name, matches))) => {
             let args = matches
-                .get_many("")
-                .map(|v| v.copied().collect())
-                .unwrap_or(Vec::new());
+                .get_many::<OsString>("")
+                .map(|v| v.collect())
+                .unwrap_or_default();
 
-            make_command(name, args)
+            make_command(name.as_ref(), args)
         }
-        None => die!("error: missing required argument <COMMAND>"),
+        _ => die!("error: missing required argument <COMMAND>"),
     };
 
     if cfg!(target_os = "windows") {
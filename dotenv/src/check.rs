@@ -0,0 +1,138 @@
+use std::env;
+use std::fmt;
+
+use crate::errors::*;
+use crate::map::EnvMap;
+
+/// The differences between a reference file, such as *.env.example*, and the
+/// actual environment.
+///
+/// Every variable declared by the reference is required to be set to a non-empty value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvDiff {
+    missing: Vec<String>,
+    undeclared: Vec<String>,
+    empty: Vec<String>,
+}
+
+impl EnvDiff {
+    /// Compares the variables declared by `reference` with those in `actual`.
+    pub fn new(reference: &EnvMap, actual: &EnvMap) -> Self {
+        let mut diff = EnvDiff::default();
+
+        for key in reference.keys() {
+            match actual.get(key) {
+                None => diff.missing.push(key.to_owned()),
+                Some("") => diff.empty.push(key.to_owned()),
+                Some(_) => {}
+            }
+        }
+        diff.undeclared = actual
+            .keys()
+            .filter(|key| !reference.contains_key(key))
+            .map(str::to_owned)
+            .collect();
+
+        diff
+    }
+
+    /// Variables declared by the reference that are not set.
+    pub fn missing(&self) -> &[String] {
+        &self.missing
+    }
+
+    /// Variables that are set but not declared by the reference.
+    pub fn undeclared(&self) -> &[String] {
+        &self.undeclared
+    }
+
+    /// Variables declared by the reference that are set to an empty value.
+    pub fn empty(&self) -> &[String] {
+        &self.empty
+    }
+
+    /// Returns `true` if the environment matches the reference.
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.undeclared.is_empty() && self.empty.is_empty()
+    }
+}
+
+impl fmt::Display for EnvDiff {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "environment does not match the reference")?;
+        let groups = [
+            ("missing", &self.missing),
+            ("undeclared", &self.undeclared),
+            ("empty", &self.empty),
+        ];
+        let mut separator = ":";
+        for (name, keys) in groups.iter() {
+            if !keys.is_empty() {
+                write!(fmt, "{} {} {}", separator, name, keys.join(", "))?;
+                separator = ";";
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the environment against the variables declared by `reference`.
+///
+/// A variable counts as set if it is in the process environment or in `dotenv`.
+/// Only the variables in `dotenv` are reported as undeclared, as the process
+/// environment always holds many unrelated variables.
+///
+/// Returns [`Error::Check`] if the environment does not match.
+pub fn check(reference: &EnvMap, dotenv: &EnvMap) -> Result<()> {
+    let mut actual = dotenv.clone();
+    for key in reference.keys() {
+        if let Some(value) = env::var_os(key) {
+            actual.insert(key, value.to_string_lossy());
+        }
+    }
+
+    let diff = EnvDiff::new(reference, &actual);
+    if diff.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Check(diff))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn map(vars: &[(&str, &str)]) -> EnvMap {
+        vars.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let reference = map(&[("A", ""), ("B", "example"), ("C", "")]);
+        let actual = map(&[("A", "1"), ("C", ""), ("D", "2")]);
+
+        let diff = EnvDiff::new(&reference, &actual);
+        assert_eq!(diff.missing(), ["B"]);
+        assert_eq!(diff.empty(), ["C"]);
+        assert_eq!(diff.undeclared(), ["D"]);
+        assert!(!diff.is_empty());
+    }
+
+    #[test]
+    fn test_diff_matching() {
+        let reference = map(&[("A", "")]);
+        assert!(EnvDiff::new(&reference, &map(&[("A", "1")])).is_empty());
+    }
+
+    #[test]
+    fn test_diff_display() {
+        let reference = map(&[("A", ""), ("B", "")]);
+        let actual = map(&[("B", ""), ("C", "1")]);
+
+        assert_eq!(
+            "environment does not match the reference: missing A; undeclared C; empty B",
+            EnvDiff::new(&reference, &actual).to_string()
+        );
+    }
+}
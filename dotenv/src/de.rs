@@ -0,0 +1,599 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+
+use crate::errors::*;
+use crate::map::EnvMap;
+
+type DeResult<T> = std::result::Result<T, DeserializeError>;
+
+/// Deserializes environment variables into a type implementing
+/// [`Deserialize`](serde::Deserialize).
+///
+/// Keys are matched case-insensitively against field names, including those set
+/// with `#[serde(rename)]`, after removing the [`prefix`](EnvDeserializer::prefix).
+/// A value read from variables whose names differ only in case, such as `PORT` and
+/// `port`, fails to deserialize rather than one of them being picked. Nested structs are reached by joining field
+/// names with the [`separator`](EnvDeserializer::separator), so with the prefix
+/// `APP_` the variable `APP_DB__HOST` fills `db.host`. Sequences are read from a
+/// single variable split on the [`list_separator`](EnvDeserializer::list_separator).
+/// `Option` fields are `None` when the variable is missing or empty.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "serde")] {
+/// use dotenvy::EnvDeserializer;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Config {
+///     port: u16,
+///     hosts: Vec<String>,
+///     db: Db,
+/// }
+///
+/// #[derive(Deserialize)]
+/// struct Db {
+///     url: String,
+///     pool: Option<u32>,
+/// }
+///
+/// let map = dotenvy::from_read_map(
+///     "APP_PORT=8080\nAPP_HOSTS=a.com,b.com\nAPP_DB__URL=postgres://localhost".as_bytes(),
+/// )
+/// .unwrap();
+///
+/// let config: Config = EnvDeserializer::new().prefix("APP_").from_map(&map).unwrap();
+/// assert_eq!(config.port, 8080);
+/// assert_eq!(config.hosts, vec!["a.com", "b.com"]);
+/// assert_eq!(config.db.pool, None);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct EnvDeserializer {
+    prefix: String,
+    separator: String,
+    list_separator: char,
+}
+
+impl EnvDeserializer {
+    /// Creates a deserializer with no prefix, `__` as the separator for nested
+    /// structs and `,` as the separator for sequences.
+    pub fn new() -> Self {
+        EnvDeserializer {
+            prefix: String::new(),
+            separator: String::from("__"),
+            list_separator: ',',
+        }
+    }
+
+    /// Only considers variables starting with `prefix`, and removes it from their names.
+    pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets the separator between the field names of nested structs.
+    pub fn separator<S: Into<String>>(mut self, separator: S) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Sets the separator between the items of a sequence.
+    pub fn list_separator(mut self, separator: char) -> Self {
+        self.list_separator = separator;
+        self
+    }
+
+    /// Deserializes `T` from the environment variables of the current process.
+    ///
+    /// Like [`var`](crate::var), the *.env* file is loaded on first use.
+    pub fn from_env<T: DeserializeOwned>(&self) -> Result<T> {
+        crate::START.call_once(|| {
+            crate::dotenv_os().ok();
+        });
+        self.from_vars(
+            env::vars_os().filter_map(|(key, value)| {
+                Some((key.into_string().ok()?, value.into_string().ok()?))
+            }),
+        )
+    }
+
+    /// Deserializes `T` from an [`EnvMap`], such as one parsed from a *.env* file.
+    pub fn from_map<T: DeserializeOwned>(&self, map: &EnvMap) -> Result<T> {
+        self.from_vars(map.iter())
+    }
+
+    /// Deserializes `T` from `(key, value)` pairs.
+    pub fn from_vars<T, I, K, V>(&self, vars: I) -> Result<T>
+    where
+        T: DeserializeOwned,
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        let mut root = Node::default();
+        for (key, value) in vars {
+            let key = key.as_ref();
+            let name = match key.strip_prefix(self.prefix.as_str()) {
+                Some(name) if !name.is_empty() => name,
+                _ => continue,
+            };
+            let node = name
+                .split(self.separator.as_str())
+                .fold(&mut root, |node, field| {
+                    node.children.entry(field.to_lowercase()).or_default()
+                });
+            if let Some((other, _)) = &node.value {
+                if other != key {
+                    node.conflict = Some(other.clone());
+                }
+            }
+            node.value = Some((key.to_owned(), value.into()));
+        }
+
+        T::deserialize(NodeDeserializer {
+            node: &root,
+            name: self.prefix.clone(),
+            options: self,
+        })
+        .map_err(Error::Deserialize)
+    }
+}
+
+impl Default for EnvDeserializer {
+    fn default() -> Self {
+        EnvDeserializer::new()
+    }
+}
+
+/// Deserializes `T` from the environment variables of the current process.
+///
+/// See [`EnvDeserializer`] for how variables are mapped onto `T`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "serde")] {
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Config {
+///     database_url: String,
+///     port: u16,
+/// }
+///
+/// let config: Config = dotenvy::from_env().unwrap();
+/// # }
+/// ```
+pub fn from_env<T: DeserializeOwned>() -> Result<T> {
+    EnvDeserializer::new().from_env()
+}
+
+/// Deserializes `T` from the environment variables of the current process that
+/// start with `prefix`.
+///
+/// See [`EnvDeserializer`] for how variables are mapped onto `T`.
+pub fn from_env_prefixed<T: DeserializeOwned>(prefix: &str) -> Result<T> {
+    EnvDeserializer::new().prefix(prefix).from_env()
+}
+
+/// Deserializes `T` from an [`EnvMap`], such as one parsed from a *.env* file.
+///
+/// See [`EnvDeserializer`] for how variables are mapped onto `T`.
+pub fn from_map<T: DeserializeOwned>(map: &EnvMap) -> Result<T> {
+    EnvDeserializer::new().from_map(map)
+}
+
+/// An error deserializing environment variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeserializeError {
+    key: Option<String>,
+    message: String,
+    missing: bool,
+}
+
+impl DeserializeError {
+    /// The variable that failed to deserialize, if known.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    /// Is this error because a required variable was not set?
+    pub fn missing(&self) -> bool {
+        self.missing
+    }
+
+    fn invalid(key: &str, value: &str, err: impl fmt::Display) -> Self {
+        DeserializeError {
+            key: Some(key.to_owned()),
+            message: format!("invalid value `{}`: {}", value, err),
+            missing: false,
+        }
+    }
+}
+
+impl de::Error for DeserializeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DeserializeError {
+            key: None,
+            message: msg.to_string(),
+            missing: false,
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        DeserializeError {
+            key: None,
+            message: field.to_owned(),
+            missing: true,
+        }
+    }
+}
+
+impl error::Error for DeserializeError {}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.key, self.missing) {
+            (Some(key), true) => write!(fmt, "missing environment variable `{}`", key),
+            (Some(key), false) => write!(fmt, "environment variable `{}`: {}", key, self.message),
+            (None, true) => write!(fmt, "missing field `{}`", self.message),
+            (None, false) => write!(fmt, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    /// The full variable name and its value.
+    value: Option<(String, String)>,
+    /// Another variable whose name only differs in case.
+    conflict: Option<String>,
+    children: BTreeMap<String, Node>,
+}
+
+struct NodeDeserializer<'a> {
+    node: &'a Node,
+    /// The variable name this node corresponds to, used in errors.
+    name: String,
+    options: &'a EnvDeserializer,
+}
+
+impl<'a> NodeDeserializer<'a> {
+    fn value(&self) -> DeResult<&'a str> {
+        match &self.node.value {
+            Some((key, _)) if self.node.conflict.is_some() => Err(DeserializeError {
+                key: Some(key.clone()),
+                message: format!(
+                    "conflicts with `{}`, which differs only in case",
+                    self.node.conflict.as_deref().unwrap_or_default()
+                ),
+                missing: false,
+            }),
+            Some((_, value)) => Ok(value),
+            None => Err(DeserializeError {
+                key: Some(self.name.clone()),
+                message: String::new(),
+                missing: true,
+            }),
+        }
+    }
+
+    fn parse<T>(&self) -> DeResult<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self.value()?;
+        value
+            .trim()
+            .parse()
+            .map_err(|err| DeserializeError::invalid(self.key(), value, err))
+    }
+
+    /// The name of the variable as it was defined, falling back to the name it
+    /// is expected to have.
+    fn key(&self) -> &str {
+        match &self.node.value {
+            Some((key, _)) => key,
+            None => &self.name,
+        }
+    }
+
+    fn child(&self, field: &str) -> String {
+        if self.name == self.options.prefix {
+            format!("{}{}", self.name, field.to_uppercase())
+        } else {
+            format!(
+                "{}{}{}",
+                self.name,
+                self.options.separator,
+                field.to_uppercase()
+            )
+        }
+    }
+
+    /// Visits the children as a map, naming those matching one of `fields` like it.
+    fn visit_children<'de, V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> DeResult<V::Value> {
+        let options = self.options;
+        let entries = self.node.children.iter().map(|(name, node)| {
+            // children are named in lowercase, so fields are compared the same way
+            let field = fields
+                .iter()
+                .copied()
+                .find(|field| field.to_lowercase() == *name)
+                .unwrap_or(name);
+            (
+                field,
+                NodeDeserializer {
+                    node,
+                    name: self.child(field),
+                    options,
+                },
+            )
+        });
+        visitor
+            .visit_map(de::value::MapDeserializer::new(entries))
+            .map_err(|err| self.name_missing(err))
+    }
+
+    /// Names the variable behind a missing field, which serde only reports by field name.
+    fn name_missing(&self, mut err: DeserializeError) -> DeserializeError {
+        if err.missing && err.key.is_none() {
+            err.key = Some(self.child(&err.message));
+        }
+        err
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for NodeDeserializer<'a> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        if self.node.children.is_empty() {
+            visitor.visit_str(self.value()?)
+        } else {
+            self.deserialize_map(visitor)
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        let value = self.value()?;
+        match value.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => visitor.visit_bool(true),
+            "false" | "0" | "no" | "off" => visitor.visit_bool(false),
+            _ => Err(DeserializeError::invalid(
+                self.key(),
+                value,
+                "expected one of true, false, 1, 0, yes, no, on or off",
+            )),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_str(self.value()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_bytes(self.value()?.as_bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        match &self.node.value {
+            Some((_, value)) if value.is_empty() && self.node.children.is_empty() => {
+                visitor.visit_none()
+            }
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> DeResult<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> DeResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        let items = self
+            .value()?
+            .split(self.options.list_separator)
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| ItemDeserializer {
+                item,
+                name: self.key(),
+            });
+        visitor.visit_seq(de::value::SeqDeserializer::new(items))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> DeResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> DeResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        self.visit_children(&[], visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> DeResult<V::Value> {
+        self.visit_children(fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> DeResult<V::Value> {
+        let value = self.value()?;
+        visitor
+            .visit_enum(value.trim().into_deserializer())
+            .map_err(|err: DeserializeError| DeserializeError::invalid(self.key(), value, err))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_unit()
+    }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, DeserializeError> for NodeDeserializer<'a> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Deserializes one item of a sequence.
+struct ItemDeserializer<'a> {
+    item: &'a str,
+    name: &'a str,
+}
+
+impl<'a> ItemDeserializer<'a> {
+    fn parse<T>(&self) -> DeResult<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.item
+            .parse()
+            .map_err(|err| DeserializeError::invalid(self.name, self.item, err))
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for ItemDeserializer<'a> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_str(self.item)
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> DeResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> DeResult<V::Value> {
+        visitor
+            .visit_enum(self.item.into_deserializer())
+            .map_err(|err: DeserializeError| DeserializeError::invalid(self.name, self.item, err))
+    }
+
+    serde::forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, DeserializeError> for ItemDeserializer<'a> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
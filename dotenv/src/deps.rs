@@ -0,0 +1,332 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::errors::*;
+use crate::parse::{Entry, Substitution, Unexpanded};
+use crate::resolver::Resolver;
+
+/// A line read ahead of substitution.
+pub enum Pending {
+    /// A definition on the given line.
+    Parsed(usize, Unexpanded),
+    /// A line that could not be read or parsed.
+    Failed(Error),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Unvisited,
+    Visiting,
+    Done,
+}
+
+/// The dependencies between the definitions of a file.
+struct Graph {
+    keys: Vec<Option<String>>,
+    /// For each line, the variables it refers to and the line defining each of
+    /// them, if any.
+    references: Vec<Vec<(String, Option<usize>)>>,
+    states: Vec<State>,
+    /// The lines being visited, each with the next of its references to follow.
+    stack: Vec<(usize, usize)>,
+    order: Vec<usize>,
+    cycles: Vec<Option<Vec<String>>>,
+}
+
+impl Graph {
+    fn new(lines: &[Pending]) -> Self {
+        let mut definitions: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (index, line) in lines.iter().enumerate() {
+            if let Pending::Parsed(_, unexpanded) = line {
+                definitions.entry(&unexpanded.key).or_default().push(index);
+            }
+        }
+
+        // A reference resolves to the closest definition above it, as when
+        // parsing line by line, or else to the first one below it.
+        let target = |name: &str, index: usize| {
+            let candidates = definitions.get(name)?;
+            candidates
+                .iter()
+                .rev()
+                .find(|&&candidate| candidate < index)
+                .or_else(|| candidates.iter().find(|&&candidate| candidate > index))
+                .copied()
+        };
+
+        let mut keys = Vec::with_capacity(lines.len());
+        let mut references = Vec::with_capacity(lines.len());
+        for (index, line) in lines.iter().enumerate() {
+            match line {
+                Pending::Parsed(_, unexpanded) => {
+                    keys.push(Some(unexpanded.key.clone()));
+                    references.push(
+                        unexpanded
+                            .references()
+                            .into_iter()
+                            .map(|name| (name.to_owned(), target(name, index)))
+                            .collect(),
+                    );
+                }
+                Pending::Failed(_) => {
+                    keys.push(None);
+                    references.push(Vec::new());
+                }
+            }
+        }
+
+        Graph {
+            keys,
+            references,
+            states: vec![State::Unvisited; lines.len()],
+            stack: Vec::new(),
+            order: Vec::with_capacity(lines.len()),
+            cycles: vec![None; lines.len()],
+        }
+    }
+
+    /// Sorts the lines so that each one comes after those it refers to,
+    /// marking the lines that are part of a cycle.
+    fn sort(&mut self) {
+        for index in 0..self.keys.len() {
+            if self.states[index] == State::Unvisited {
+                self.visit(index);
+            }
+        }
+    }
+
+    /// Visits the lines reachable from `start` depth first, without recursing
+    /// so that long chains of references cannot overflow the stack.
+    fn visit(&mut self, start: usize) {
+        self.enter(start);
+
+        while let Some(&(index, reference)) = self.stack.last() {
+            let target = match self.references[index].get(reference) {
+                Some(&(_, target)) => target,
+                None => {
+                    self.stack.pop();
+                    self.states[index] = State::Done;
+                    self.order.push(index);
+                    continue;
+                }
+            };
+            if let Some(frame) = self.stack.last_mut() {
+                frame.1 += 1;
+            }
+
+            match target.map(|target| (target, self.states[target])) {
+                Some((target, State::Unvisited)) => self.enter(target),
+                Some((target, State::Visiting)) => self.mark_cycle(target),
+                Some((_, State::Done)) | None => {}
+            }
+        }
+    }
+
+    fn enter(&mut self, index: usize) {
+        self.states[index] = State::Visiting;
+        self.stack.push((index, 0));
+    }
+
+    /// Marks the lines on the stack from `start` onwards as a cycle.
+    fn mark_cycle(&mut self, start: usize) {
+        let position = self
+            .stack
+            .iter()
+            .position(|&(index, _)| index == start)
+            .unwrap_or_default();
+        let members: Vec<usize> = self.stack[position..]
+            .iter()
+            .map(|&(index, _)| index)
+            .collect();
+
+        let mut cycle: Vec<String> = members
+            .iter()
+            .filter_map(|&index| self.keys[index].clone())
+            .collect();
+        cycle.extend(self.keys[start].clone());
+
+        for index in members {
+            self.cycles[index] = Some(cycle.clone());
+        }
+    }
+}
+
+/// Substitutes the values of `lines` in dependency order, so that definitions
+/// may refer to those below them.
+///
+/// The results are returned in the order of `lines`, as are the references
+/// recorded by [`UndefinedVars::Collect`](crate::UndefinedVars::Collect).
+/// Definitions that are part of a cycle fail with [`Error::CyclicSubstitution`],
+/// and references to them are treated as undefined.
+///
+/// Every reference in a value is a dependency, including those in the word of
+/// an operator that ends up unused, such as `B` in `A=${C:-$B}`. Such a
+/// definition may therefore fail as part of a cycle although `C` is set.
+pub fn expand_in_order(
+    lines: Vec<Pending>,
+    substitution: &mut Substitution,
+    resolver: &dyn Resolver,
+) -> VecDeque<Result<(usize, Entry)>> {
+    let mut graph = Graph::new(&lines);
+    graph.sort();
+
+    let seed = substitution.data.clone();
+    let recorded = substitution.unresolved.len();
+    let mut lines: Vec<Option<Pending>> = lines.into_iter().map(Some).collect();
+    let mut values: Vec<Option<String>> = vec![None; lines.len()];
+    let mut results: Vec<Option<Result<(usize, Entry)>>> = (0..lines.len()).map(|_| None).collect();
+
+    for &index in &graph.order {
+        let (line, unexpanded) = match lines[index].take() {
+            Some(Pending::Parsed(line, unexpanded)) => (line, unexpanded),
+            Some(Pending::Failed(err)) => {
+                results[index] = Some(Err(err));
+                continue;
+            }
+            None => continue,
+        };
+        if let Some(cycle) = graph.cycles[index].take() {
+            results[index] = Some(Err(Error::CyclicSubstitution(cycle)));
+            continue;
+        }
+
+        for (name, target) in &graph.references[index] {
+            let value = match target {
+                Some(target) => values[*target].clone().map(Some),
+                None => seed.get(name).cloned(),
+            };
+            match value {
+                Some(value) => substitution.data.insert(name.clone(), value),
+                None => substitution.data.remove(name),
+            };
+        }
+
+        let result = substitution.expand_entry(unexpanded, line, resolver);
+        if let Ok(entry) = &result {
+            values[index] = Some(entry.value.clone());
+        }
+        results[index] = Some(result.map(|entry| (line, entry)));
+    }
+
+    substitution.unresolved[recorded..].sort_by_key(|unresolved| unresolved.line);
+
+    // leave the last definition of each key available, as when parsing line by line
+    for (key, value) in graph.keys.iter().zip(values) {
+        if let (Some(key), Some(value)) = (key, value) {
+            substitution.data.insert(key.clone(), Some(value));
+        }
+    }
+
+    results.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::{parse_unexpanded, KeyPolicy, UndefinedVars};
+    use crate::resolver::Isolated;
+
+    /// Expands `lines`, one definition each, returning the value of each line.
+    fn expand(lines: &[&str], substitution: &mut Substitution) -> Vec<Result<String>> {
+        let lines = lines
+            .iter()
+            .enumerate()
+            .map(
+                |(index, line)| match parse_unexpanded(line, true, false, KeyPolicy::Default) {
+                    Ok(Some(unexpanded)) => Pending::Parsed(index + 1, unexpanded),
+                    Ok(None) => panic!("no definition in {:?}", line),
+                    Err(err) => Pending::Failed(err),
+                },
+            )
+            .collect();
+        expand_in_order(lines, substitution, &Isolated)
+            .into_iter()
+            .map(|result| result.map(|(_, entry)| entry.value))
+            .collect()
+    }
+
+    fn values(lines: &[&str]) -> Vec<String> {
+        expand(lines, &mut Substitution::default())
+            .into_iter()
+            .map(|result| result.unwrap())
+            .collect()
+    }
+
+    fn cycle(result: &Result<String>) -> &[String] {
+        match result {
+            Err(Error::CyclicSubstitution(cycle)) => cycle,
+            result => panic!("expected a cycle, got: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_forward_references() {
+        assert_eq!(
+            values(&["A=${B}-a", "B=${C}-b", "C=c"]),
+            ["c-b-a", "c-b", "c"]
+        );
+    }
+
+    #[test]
+    fn test_self_reference() {
+        assert_eq!(values(&["A=${A}x"]), ["x"]);
+        assert_eq!(values(&["A=1", "A=${A}2"]), ["1", "12"]);
+    }
+
+    #[test]
+    fn test_cycle() {
+        let results = expand(
+            &["A=${B}", "B=${A}", "C=c${A}"],
+            &mut Substitution::default(),
+        );
+        assert_eq!(cycle(&results[0]), ["A", "B", "A"]);
+        assert_eq!(cycle(&results[1]), ["A", "B", "A"]);
+        assert_eq!(results[2].as_ref().unwrap(), "c");
+    }
+
+    #[test]
+    fn test_key_defined_twice() {
+        let mut substitution = Substitution::default();
+        let results = expand(
+            &["B=${A}", "A=1", "C=${A}", "A=2", "D=${A}"],
+            &mut substitution,
+        );
+        let results: Vec<_> = results.into_iter().map(|result| result.unwrap()).collect();
+        assert_eq!(results, ["1", "1", "1", "2", "2"]);
+        assert_eq!(substitution.data.get("A"), Some(&Some("2".to_owned())));
+    }
+
+    #[test]
+    fn test_long_chain() {
+        let lines: Vec<String> = (0..100_000)
+            .map(|index| format!("V{}=${{V{}}}", index, index + 1))
+            .collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        assert!(values(&lines).iter().all(String::is_empty));
+    }
+
+    #[test]
+    fn test_cycle_through_unused_word() {
+        let results = expand(
+            &["C=c", "A=${C:-$B}", "B=${A}"],
+            &mut Substitution::default(),
+        );
+        assert_eq!(results[0].as_ref().unwrap(), "c");
+        assert_eq!(cycle(&results[1]), ["A", "B", "A"]);
+        assert_eq!(cycle(&results[2]), ["A", "B", "A"]);
+    }
+
+    #[test]
+    fn test_unresolved_in_line_order() {
+        let mut substitution = Substitution {
+            undefined: UndefinedVars::Collect,
+            ..Substitution::default()
+        };
+        expand(&["A=${X}${B}", "B=${Y}", "C=${Z}"], &mut substitution);
+        let unresolved: Vec<_> = substitution
+            .unresolved
+            .iter()
+            .map(|unresolved| (unresolved.line(), unresolved.var()))
+            .collect();
+        assert_eq!(unresolved, [(1, "X"), (2, "Y"), (3, "Z")]);
+    }
+}
@@ -0,0 +1,1000 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+use crate::errors::*;
+use crate::iter::{Iter, QuotedLines};
+use crate::loader::Precedence;
+use crate::parse::{self, KeyPolicy};
+use crate::resolver::Resolver;
+use crate::ser::{check_key, check_value, invalid_key, quote};
+
+/// The key policy of documents, which accept any key so that files written for
+/// any other policy can be edited.
+pub(crate) const ANY_KEY: KeyPolicy = KeyPolicy::Custom(|_| true);
+
+/// A *.env* file parsed without losing anything, so that it can be inspected
+/// and written back as it was.
+///
+/// Comments, blank lines, `export` prefixes, quotes and spacing are all kept,
+/// and displaying an unmodified document gives back the original text byte
+/// for byte. Any file that [`Iter`] accepts can be parsed,
+/// whatever its [`KeyPolicy`].
+///
+/// Values are not substituted, so `$` is taken literally.
+///
+/// Documents can be edited with [`set`](Document::set),
+/// [`remove`](Document::remove), [`rename`](Document::rename) and
+/// [`insert_after`](Document::insert_after), which leave the other lines as they
+/// were, and written back with [`save`](Document::save). They can also be
+/// normalised with [`format`](Document::format) and
+/// [`sort_groups`](Document::sort_groups).
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), dotenvy::Error> {
+/// use dotenvy::{Document, Quote};
+///
+/// let text = "# database\nexport DB_HOST = 'localhost'  # local only\n\nDB_PORT=5432\n";
+/// let document: Document = text.parse()?;
+///
+/// let host = document.get("DB_HOST").unwrap();
+/// assert_eq!(host.value(), "localhost");
+/// assert_eq!(host.quote(), Quote::Single);
+/// assert!(host.is_exported());
+/// assert_eq!(host.comment(), Some(" local only"));
+///
+/// assert_eq!(document.to_string(), text);
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Document {
+    /// Whether the text starts with a byte order mark.
+    bom: bool,
+    items: Vec<Item>,
+}
+
+/// A logical line of a [`Document`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    /// A line holding nothing but whitespace.
+    Blank(Trivia),
+    /// A line holding only a comment.
+    Comment(Trivia),
+    /// A variable definition, which may span several lines.
+    Entry(Entry),
+}
+
+/// The text of a blank or comment line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trivia {
+    text: String,
+    line_ending: String,
+}
+
+/// A variable definition in a [`Document`], split into the parts it was
+/// written with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    indent: String,
+    /// `export` and the whitespace following it, if exported.
+    export: String,
+    key: String,
+    /// The `=` and the whitespace around it.
+    pub(crate) separator: String,
+    raw_value: String,
+    value: String,
+    quote: Quote,
+    /// The whitespace and comment following the value.
+    pub(crate) trailing: String,
+    pub(crate) line_ending: String,
+}
+
+/// How the value of an [`Entry`] is quoted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quote {
+    /// Not quoted, including empty values.
+    Unquoted,
+    /// Single quotes, `'...'`.
+    Single,
+    /// Double quotes, `"..."`.
+    Double,
+    /// Backticks, `` `...` ``.
+    Backtick,
+    /// A heredoc, `<<EOF`.
+    Heredoc,
+}
+
+impl Document {
+    /// Parses `text`, failing like [`Iter`] on lines it cannot
+    /// parse.
+    pub fn parse(text: &str) -> Result<Document> {
+        Document::parse_named(text, None)
+    }
+
+    /// Reads and parses the file at `path`, naming it in parse errors.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Document> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(Error::Io)?;
+        Document::parse_named(&text, Some(&path.display().to_string()))
+    }
+
+    fn parse_named(text: &str, source_name: Option<&str>) -> Result<Document> {
+        let (bom, text) = strip_bom(text);
+        let items = Items::new(text, source_name)
+            .map(|(_, item)| item)
+            .collect::<Result<_>>()?;
+        Ok(Document { bom, items })
+    }
+
+    /// Returns every line of the document, in order.
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    /// Returns every definition, in order.
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Entry(entry) => Some(entry),
+            _ => None,
+        })
+    }
+
+    /// Returns the last definition of `key`, the one that is loaded.
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries().filter(|entry| entry.key == key).last()
+    }
+
+    /// Sets `key` to `value`, taken literally.
+    ///
+    /// If `key` is defined, its last definition is updated in place, keeping
+    /// its quotes if they can hold the new value. Otherwise the definition is
+    /// added at the end. The value is left unquoted if that is safe, and put in
+    /// single quotes, or else double quotes, if not, as with
+    /// [`to_string`](crate::to_string).
+    ///
+    /// Fails if `key` is not defined and is not accepted by
+    /// [`KeyPolicy::Permissive`], or if `value` contains a NUL character.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), dotenvy::Error> {
+    /// use dotenvy::Document;
+    ///
+    /// let mut document: Document = "# the greeting\nGREETING=\"hi\" # shown on start\n".parse()?;
+    /// document.set("GREETING", "hello world")?;
+    /// document.set("NAME", "it's me")?;
+    ///
+    /// assert_eq!(
+    ///     document.to_string(),
+    ///     "# the greeting\nGREETING=\"hello world\" # shown on start\nNAME=\"it's me\"\n"
+    /// );
+    /// #     Ok(())
+    /// # }
+    /// ```
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        check_value(value)?;
+        let index = match self.position(key) {
+            Some(index) => index,
+            None => return self.insert_entry(self.items.len(), key, value),
+        };
+        if let Item::Entry(entry) = &mut self.items[index] {
+            let updated = Entry {
+                raw_value: quote(value, entry.quote),
+                ..entry.clone()
+            };
+            *entry = Entry::parse(&updated.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Removes every definition of `key`, returning whether there was any.
+    ///
+    /// Comments above the definitions are kept.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let len = self.items.len();
+        self.items
+            .retain(|item| !matches!(item, Item::Entry(entry) if entry.key == key));
+        self.items.len() != len
+    }
+
+    /// Renames every definition of `from` to `to`, returning whether there was
+    /// any.
+    ///
+    /// References to `from` in other values are not updated, and definitions
+    /// of `to` are kept. Fails if `to` is not accepted by
+    /// [`KeyPolicy::Permissive`].
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<bool> {
+        check_key(to, KeyPolicy::Permissive)?;
+        let mut found = false;
+        for item in &mut self.items {
+            if let Item::Entry(entry) = item {
+                if entry.key == from {
+                    entry.key = to.to_owned();
+                    found = true;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Adds a definition of `key` right after the last definition of `after`,
+    /// returning whether `after` is defined.
+    ///
+    /// The value is quoted as with [`set`](Document::set). Nothing is added if
+    /// `after` is not defined. Fails if `key` is not accepted by
+    /// [`KeyPolicy::Permissive`].
+    pub fn insert_after(&mut self, after: &str, key: &str, value: &str) -> Result<bool> {
+        match self.position(after) {
+            Some(index) => self.insert_entry(index + 1, key, value).map(|()| true),
+            None => {
+                check_key(key, KeyPolicy::Permissive)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Writes the document to the file at `path`, replacing it atomically.
+    ///
+    /// The document is written to a temporary file next to it, which is then
+    /// renamed over it, so that the file is never left half written. The
+    /// permissions of the existing file are kept, and symbolic links are
+    /// followed.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = fs::canonicalize(path.as_ref()).unwrap_or_else(|_| path.as_ref().to_owned());
+        let name = path.file_name().ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the path does not name a file",
+            ))
+        })?;
+        let mut temp_name = OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".{}.tmp", process::id()));
+        let temp_path = path.with_file_name(temp_name);
+
+        let write = || -> io::Result<()> {
+            let mut file = File::create(&temp_path)?;
+            // before writing, so that secrets are never readable by others
+            if let Ok(metadata) = fs::metadata(&path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.write_all(self.to_string().as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temp_path, &path)
+        };
+        write().map_err(|err| {
+            let _ = fs::remove_file(&temp_path);
+            Error::Io(err)
+        })
+    }
+
+    /// Normalises the layout of the document without changing the values it
+    /// defines.
+    ///
+    /// Indentation, spacing around `=` and trailing whitespace are removed,
+    /// `export` is followed by a single space, and comments after a value are
+    /// preceded by a single space. Values are quoted as with
+    /// [`set`](Document::set), except for heredocs and values holding control
+    /// characters, which are kept as written, and values referring to
+    /// variables, whose quotes decide whether they are substituted.
+    ///
+    /// Each change is checked by parsing the definition before and after it,
+    /// so that a definition is left as it was rather than read differently.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), dotenvy::Error> {
+    /// use dotenvy::Document;
+    ///
+    /// let mut document: Document = "  export  HOST = \"localhost\"   # dev\nURL=\"http://$HOST\"  \n".parse()?;
+    /// document.format();
+    /// assert_eq!(
+    ///     document.to_string(),
+    ///     "export HOST=localhost # dev\nURL=\"http://$HOST\"\n"
+    /// );
+    /// #     Ok(())
+    /// # }
+    /// ```
+    pub fn format(&mut self) {
+        for item in &mut self.items {
+            match item {
+                Item::Blank(trivia) => trivia.text.clear(),
+                Item::Comment(trivia) => trivia.text = trivia.text.trim().to_owned(),
+                Item::Entry(entry) => *entry = entry.formatted(),
+            }
+        }
+    }
+
+    /// Sorts the definitions of each group by key, a group being a run of
+    /// definitions between blank or comment lines.
+    ///
+    /// A group is left as it was if sorting it would change the values the
+    /// document defines, for instance because a definition refers to the one
+    /// above it.
+    pub fn sort_groups(&mut self) {
+        let mut start = 0;
+        while start < self.items.len() {
+            let len = self.items[start..]
+                .iter()
+                .take_while(|item| matches!(item, Item::Entry(_)))
+                .count();
+            if len > 1 {
+                let before = resolved(&self.to_string());
+                let group = self.items[start..start + len].to_vec();
+                // the last line of the document may have no line ending
+                let last_line_ending = group[len - 1].line_ending().to_owned();
+                let line_ending = self.line_ending();
+                self.items[start..start + len].sort_by(|a, b| match (a, b) {
+                    (Item::Entry(a), Item::Entry(b)) => a.key.cmp(&b.key),
+                    _ => std::cmp::Ordering::Equal,
+                });
+                for item in &mut self.items[start..start + len - 1] {
+                    if item.line_ending().is_empty() {
+                        item.set_line_ending(line_ending);
+                    }
+                }
+                self.items[start + len - 1].set_line_ending(&last_line_ending);
+
+                if before.is_none() || resolved(&self.to_string()) != before {
+                    self.items.splice(start..start + len, group);
+                }
+            }
+            start += len.max(1);
+        }
+    }
+
+    /// Returns the index of the last definition of `key`.
+    fn position(&self, key: &str) -> Option<usize> {
+        self.items
+            .iter()
+            .rposition(|item| matches!(item, Item::Entry(entry) if entry.key == key))
+    }
+
+    /// Adds a new definition at `index`, ending the line before it if needed.
+    fn insert_entry(&mut self, index: usize, key: &str, value: &str) -> Result<()> {
+        check_key(key, KeyPolicy::Permissive)?;
+        check_value(value)?;
+        let line_ending = self.line_ending();
+        let mut entry = Entry::parse(&format!("{}={}", key, quote(value, Quote::Unquoted)))?;
+        match index
+            .checked_sub(1)
+            .map(|previous| &mut self.items[previous])
+        {
+            // the last line has no line ending, and neither will the new one
+            Some(previous) if previous.line_ending().is_empty() => {
+                previous.set_line_ending(line_ending)
+            }
+            _ => entry.line_ending = line_ending.to_owned(),
+        }
+        self.items.insert(index, Item::Entry(entry));
+        Ok(())
+    }
+
+    /// Returns the line ending used by the document, `\n` unless the first line
+    /// ends with `\r\n`.
+    fn line_ending(&self) -> &'static str {
+        match self
+            .items
+            .iter()
+            .map(Item::line_ending)
+            .find(|ending| !ending.is_empty())
+        {
+            Some("\r\n") => "\r\n",
+            _ => "\n",
+        }
+    }
+}
+
+/// The logical lines of a text, parsed one at a time along with the line each
+/// starts on, so that parsing can go on after a line fails.
+pub(crate) struct Items<'a> {
+    physical: Vec<&'a str>,
+    lines: QuotedLines<&'a [u8]>,
+    /// The number of physical lines parsed so far.
+    consumed: usize,
+    source_name: Option<&'a str>,
+}
+
+impl<'a> Items<'a> {
+    /// Parses `text`, which must not start with a byte order mark.
+    pub(crate) fn new(text: &'a str, source_name: Option<&'a str>) -> Self {
+        Items {
+            physical: text.split_inclusive('\n').collect(),
+            lines: QuotedLines::new(text.as_bytes()),
+            consumed: 0,
+            source_name,
+        }
+    }
+
+    /// Adds where a parse error happened, `logical` being the line it
+    /// happened in unless already known.
+    fn locate(&self, err: Error, logical: Option<&str>) -> Error {
+        match err {
+            Error::Parse(mut err) => {
+                if let Some(logical) = logical {
+                    err.locate(logical, self.lines.start_line);
+                }
+                err.set_source_name(self.source_name);
+                Error::Parse(err)
+            }
+            err => err,
+        }
+    }
+}
+
+impl<'a> Iterator for Items<'a> {
+    type Item = (usize, Result<Item>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let logical = self.lines.next()?;
+        let start_line = self.lines.start_line;
+        let raw = self.physical[self.consumed..self.lines.line].concat();
+        self.consumed = self.lines.line;
+
+        let logical = match logical {
+            Ok(logical) => logical,
+            Err(err) => return Some((start_line, Err(self.locate(err, None)))),
+        };
+        let item = match parse::parse_unexpanded(&logical, false, false, ANY_KEY) {
+            Ok(Some(unexpanded)) => Item::Entry(Entry::split(&raw, unexpanded)),
+            Ok(None) => {
+                let (text, line_ending) = split_line_ending(&raw);
+                let trivia = Trivia {
+                    text: text.to_owned(),
+                    line_ending: line_ending.to_owned(),
+                };
+                if text.trim_start().starts_with('#') {
+                    Item::Comment(trivia)
+                } else {
+                    Item::Blank(trivia)
+                }
+            }
+            Err(err) => return Some((start_line, Err(self.locate(err, Some(&logical))))),
+        };
+        Some((start_line, Ok(item)))
+    }
+}
+
+impl FromStr for Document {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Document> {
+        Document::parse(text)
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.bom {
+            write!(fmt, "\u{feff}")?;
+        }
+        self.items
+            .iter()
+            .try_for_each(|item| write!(fmt, "{}", item))
+    }
+}
+
+impl Item {
+    fn line_ending(&self) -> &str {
+        match self {
+            Item::Blank(trivia) | Item::Comment(trivia) => &trivia.line_ending,
+            Item::Entry(entry) => &entry.line_ending,
+        }
+    }
+
+    fn set_line_ending(&mut self, line_ending: &str) {
+        match self {
+            Item::Blank(trivia) | Item::Comment(trivia) => {
+                trivia.line_ending = line_ending.to_owned()
+            }
+            Item::Entry(entry) => entry.line_ending = line_ending.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Item::Blank(trivia) | Item::Comment(trivia) => write!(fmt, "{}", trivia),
+            Item::Entry(entry) => write!(fmt, "{}", entry),
+        }
+    }
+}
+
+impl Trivia {
+    /// The text of the line, without its line ending.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl fmt::Display for Trivia {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}{}", self.text, self.line_ending)
+    }
+}
+
+impl Entry {
+    /// Parses the text of a single definition.
+    fn parse(text: &str) -> Result<Entry> {
+        let mut document = Document::parse(text)?;
+        match document.items.pop() {
+            Some(Item::Entry(entry)) if document.items.is_empty() => Ok(entry),
+            _ => Err(invalid_key(text, KeyPolicy::Permissive)),
+        }
+    }
+
+    /// Returns the definition with its layout normalised, as described in
+    /// [`Document::format`].
+    fn formatted(&self) -> Entry {
+        let spaced = Entry {
+            indent: String::new(),
+            export: if self.is_exported() {
+                "export ".to_owned()
+            } else {
+                String::new()
+            },
+            separator: "=".to_owned(),
+            trailing: match self.comment() {
+                Some(comment) => format!(" #{}", comment.trim_end()),
+                None => String::new(),
+            },
+            ..self.clone()
+        };
+        let raw_value = quote(&self.value, Quote::Unquoted);
+        let requoted = Entry {
+            quote: Quote::of(&raw_value),
+            raw_value,
+            ..spaced.clone()
+        };
+
+        let original = resolved(&self.to_string());
+        // control characters keep the quotes and escapes they are written with
+        let keep_quotes = self.quote == Quote::Heredoc || self.value.chars().any(char::is_control);
+        let candidates = if keep_quotes {
+            vec![spaced]
+        } else {
+            vec![requoted, spaced]
+        };
+        candidates
+            .into_iter()
+            .find(|candidate| original.is_some() && resolved(&candidate.to_string()) == original)
+            .unwrap_or_else(|| self.clone())
+    }
+
+    /// Splits the `raw` text of a definition into its parts.
+    fn split(raw: &str, unexpanded: parse::Unexpanded) -> Entry {
+        let (text, line_ending) = split_line_ending(raw);
+        let (indent, rest) = split_whitespace(text);
+
+        // `export` is a prefix unless it is the key itself
+        let export_len = match rest.strip_prefix("export") {
+            Some(after) => {
+                let (space, next) = split_whitespace(after);
+                if !space.is_empty() && !next.starts_with('=') {
+                    "export".len() + space.len()
+                } else {
+                    0
+                }
+            }
+            None => 0,
+        };
+        let (export, rest) = rest.split_at(export_len);
+        let (key, rest) = rest.split_at(unexpanded.key.len());
+
+        let (before, after) = split_whitespace(rest);
+        let mut separator_len = before.len() + 1;
+        if !unexpanded.raw.is_empty() {
+            separator_len += split_whitespace(&after[1..]).0.len();
+        }
+        let (separator, rest) = rest.split_at(separator_len);
+        let (raw_value, trailing) = rest.split_at(unexpanded.raw.len());
+
+        Entry {
+            indent: indent.to_owned(),
+            export: export.to_owned(),
+            key: key.to_owned(),
+            separator: separator.to_owned(),
+            raw_value: raw_value.to_owned(),
+            value: unexpanded.literal(),
+            quote: Quote::of(raw_value),
+            trailing: trailing.to_owned(),
+            line_ending: line_ending.to_owned(),
+        }
+    }
+
+    /// The name of the variable.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The value, unquoted and with escapes decoded.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The value as written, including any quotes.
+    pub fn raw_value(&self) -> &str {
+        &self.raw_value
+    }
+
+    /// How the value is quoted.
+    pub fn quote(&self) -> Quote {
+        self.quote
+    }
+
+    /// Whether the definition starts with `export`.
+    pub fn is_exported(&self) -> bool {
+        !self.export.is_empty()
+    }
+
+    /// The comment following the value, without its `#`.
+    pub fn comment(&self) -> Option<&str> {
+        self.trailing.trim_start().strip_prefix('#')
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{}{}{}{}{}{}{}",
+            self.indent,
+            self.export,
+            self.key,
+            self.separator,
+            self.raw_value,
+            self.trailing,
+            self.line_ending
+        )
+    }
+}
+
+impl Quote {
+    fn of(raw_value: &str) -> Quote {
+        match raw_value.chars().next() {
+            Some('\'') => Quote::Single,
+            Some('"') => Quote::Double,
+            Some('`') => Quote::Backtick,
+            Some('<') if parse::heredoc_value(raw_value).is_some() => Quote::Heredoc,
+            _ => Quote::Unquoted,
+        }
+    }
+}
+
+/// Resolves every variable to a marker naming it, so that definitions reading
+/// each other differently after formatting also resolve differently.
+struct Sentinel;
+
+impl Resolver for Sentinel {
+    fn resolve(&self, name: &str) -> Option<String> {
+        Some(format!("\u{e000}{}\u{e000}", name))
+    }
+}
+
+/// Returns the values `text` defines, substituting variables not defined in
+/// `text` with [`Sentinel`] markers, or `None` if it cannot be parsed.
+fn resolved(text: &str) -> Option<BTreeMap<String, String>> {
+    let map = Iter::new(text.as_bytes())
+        .key_policy(ANY_KEY)
+        .resolver(Sentinel)
+        .substitution_precedence(Precedence::File)
+        .into_map()
+        .ok()?;
+    Some(map.into_iter().collect())
+}
+
+/// Splits a leading byte order mark off `text`, returning whether there was one.
+pub(crate) fn strip_bom(text: &str) -> (bool, &str) {
+    // https://www.compart.com/en/unicode/U+FEFF
+    match text.strip_prefix('\u{feff}') {
+        Some(text) => (true, text),
+        None => (false, text),
+    }
+}
+
+/// Splits the line ending off the end of `raw`, if any.
+fn split_line_ending(raw: &str) -> (&str, &str) {
+    let len = if raw.ends_with("\r\n") {
+        2
+    } else if raw.ends_with('\n') {
+        1
+    } else {
+        0
+    };
+    raw.split_at(raw.len() - len)
+}
+
+/// Splits the leading whitespace off `text`.
+fn split_whitespace(text: &str) -> (&str, &str) {
+    text.split_at(text.len() - text.trim_start().len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roundtrip(text: &str) -> Document {
+        let document = Document::parse(text).expect("parse");
+        assert_eq!(document.to_string(), text);
+        document
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        roundtrip("");
+        roundtrip("\n\n");
+        roundtrip("\u{feff}A=1");
+        roundtrip("  # comment \r\n\tA = 1\r\nB=2");
+        roundtrip("export A='x'\nexport=1\nexport  B=\"y\"  # c\n");
+        roundtrip("A=\"multi\nline\" # c\nB='x\r\ny'\n");
+        roundtrip("A=one\\\n    two\nB=`b`");
+        roundtrip("CERT=<<EOF\nline\n  EOF\nEOF  \nNEXT=1\n");
+        roundtrip("A=\nB= # empty\nC=$HOME/${X:-y}\n");
+        roundtrip("key-with.dots:and-dashes=1\n");
+        roundtrip("A=last\\\n");
+    }
+
+    #[test]
+    fn test_items() {
+        let document = roundtrip("# top\n\n  export A = \"a\\tb\"   # note\nB=\n");
+        let items = document.items();
+        assert_eq!(items.len(), 4);
+        assert!(matches!(&items[0], Item::Comment(trivia) if trivia.text() == "# top"));
+        assert!(matches!(&items[1], Item::Blank(trivia) if trivia.text().is_empty()));
+
+        let a = document.get("A").unwrap();
+        assert_eq!(a.value(), "a\tb");
+        assert_eq!(a.raw_value(), "\"a\\tb\"");
+        assert_eq!(a.quote(), Quote::Double);
+        assert!(a.is_exported());
+        assert_eq!(a.comment(), Some(" note"));
+
+        let b = document.get("B").unwrap();
+        assert_eq!(b.value(), "");
+        assert_eq!(b.quote(), Quote::Unquoted);
+        assert_eq!(b.comment(), None);
+    }
+
+    #[test]
+    fn test_quotes() {
+        let document = roundtrip("A=plain\nB='s'\nC=\"d\"\nD=`b`\nE=<<EOF\nh\nEOF\nF=a'b'\n");
+        let quotes: Vec<_> = document.entries().map(Entry::quote).collect();
+        assert_eq!(
+            quotes,
+            vec![
+                Quote::Unquoted,
+                Quote::Single,
+                Quote::Double,
+                Quote::Backtick,
+                Quote::Heredoc,
+                Quote::Unquoted
+            ]
+        );
+        assert_eq!(document.get("E").unwrap().value(), "h");
+        assert_eq!(document.get("F").unwrap().value(), "ab");
+    }
+
+    #[test]
+    fn test_get_returns_last_definition() {
+        let document = roundtrip("A=1\nA=2\n");
+        assert_eq!(document.entries().count(), 2);
+        assert_eq!(document.get("A").unwrap().value(), "2");
+        assert!(document.get("B").is_none());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = match Document::parse("A=1\nB='open\nC=3") {
+            Err(Error::Parse(err)) => err,
+            _ => panic!("expected a parse error"),
+        };
+        assert!(matches!(err.kind(), ParseErrorKind::UnterminatedQuote));
+        assert_eq!((err.line(), err.column()), (2, 3));
+    }
+
+    #[test]
+    fn test_set_existing() {
+        let mut document =
+            roundtrip("# c\nexport A = 'one' # keep\nB=\"two\"\r\nC=<<EOF\nold\nEOF\n");
+        document.set("A", "uno").unwrap();
+        document.set("B", "say \"$HOME\"\n").unwrap();
+        document.set("C", "new").unwrap();
+        assert_eq!(
+            document.to_string(),
+            "# c\nexport A = 'uno' # keep\nB=\"say \\\"\\$HOME\\\"\\n\"\r\nC=new\n"
+        );
+        assert_eq!(document.get("B").unwrap().value(), "say \"$HOME\"\n");
+    }
+
+    #[test]
+    fn test_set_quotes_safely() {
+        let values = [
+            "plain",
+            "",
+            "with space",
+            "it's",
+            "ends with \\",
+            "quote \\' backslash",
+            "$HOME #hash",
+            "<<EOF",
+            "a=<<EOF",
+            "multi\nline",
+            "tab\tand \"double\" `tick`",
+            "bell\x07",
+        ];
+        for value in values.iter() {
+            for &initial in ["A=x\n", "A='x'\n", "A=\"x\"\n", "A=`x`\n", "A=\n"].iter() {
+                let mut document = roundtrip(initial);
+                document.set("A", value).unwrap();
+                let reparsed = roundtrip(&document.to_string());
+                assert_eq!(
+                    reparsed.get("A").unwrap().value(),
+                    *value,
+                    "{:?}",
+                    document.to_string()
+                );
+
+                let map = crate::Iter::new(document.to_string().as_bytes())
+                    .no_substitution()
+                    .into_map()
+                    .unwrap();
+                assert_eq!(map.get("A"), Some(*value));
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_new_key() {
+        let mut document = roundtrip("A=1");
+        document.set("B", "two words").unwrap();
+        assert_eq!(document.to_string(), "A=1\nB='two words'");
+
+        let mut document = roundtrip("A=1\r\n");
+        document.set("B", "2").unwrap();
+        assert_eq!(document.to_string(), "A=1\r\nB=2\r\n");
+
+        let mut document = Document::default();
+        document.set("A", "1").unwrap();
+        assert_eq!(document.to_string(), "A=1\n");
+
+        for key in ["", "A B", "#A", "export A", "A=B"].iter() {
+            assert!(matches!(
+                document.set(key, "1"),
+                Err(Error::Parse(ref err)) if matches!(err.kind(), ParseErrorKind::InvalidKey(_))
+            ));
+        }
+        assert_eq!(document.to_string(), "A=1\n");
+
+        assert!(document.set("A", "a\0b").is_err());
+        assert!(document.set("B", "a\0b").is_err());
+        assert_eq!(document.to_string(), "A=1\n");
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut document = roundtrip("# a\nA=1\nB=2\nA=3\n");
+        assert!(document.remove("A"));
+        assert!(!document.remove("A"));
+        assert_eq!(document.to_string(), "# a\nB=2\n");
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut document = roundtrip("export OLD = 1 # c\nB=$OLD\nOLD=2");
+        assert!(document.rename("OLD", "NEW").unwrap());
+        assert!(!document.rename("OLD", "NEW").unwrap());
+        assert!(document.rename("B", "in valid").is_err());
+        assert_eq!(document.to_string(), "export NEW = 1 # c\nB=$OLD\nNEW=2");
+    }
+
+    #[test]
+    fn test_insert_after() {
+        let mut document = roundtrip("A=1\n# b\nB=2");
+        assert!(document.insert_after("A", "A2", "1.5").unwrap());
+        assert!(document.insert_after("B", "C", "3").unwrap());
+        assert!(!document.insert_after("MISSING", "D", "4").unwrap());
+        assert!(document.insert_after("MISSING", "in valid", "4").is_err());
+        assert_eq!(document.to_string(), "A=1\nA2=1.5\n# b\nB=2\nC=3");
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        fs::write(&path, "# secrets\nTOKEN=old\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+
+        let mut document = Document::from_path(&path).unwrap();
+        document.set("TOKEN", "new").unwrap();
+        document.save(&path).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# secrets\nTOKEN=new\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let new_path = dir.path().join("new.env");
+        document.save(&new_path).unwrap();
+        assert_eq!(Document::from_path(&new_path).unwrap(), document);
+    }
+
+    #[test]
+    fn test_format() {
+        let mut document = roundtrip(concat!(
+            "  # comment  \n",
+            "   \n",
+            "export   A = \"a b\"   #  note  \r\n",
+            "\tB= 'plain'\n",
+            "C =\"it's \\$5\"\n",
+            "D=\"${A}\" \n",
+            "E=one\\\n  two\n",
+            "F=<<EOF\nbody\nEOF   \n",
+            "G = \n",
+            "H='$HOME' # literal\n",
+            "I = \"l1\\nl2\"\n",
+            "J=\"x\\r\"\n",
+            "K=\"\\u00e9\\x07\"\n",
+            "L='tab\there'",
+        ));
+        let before = resolved(&document.to_string());
+        document.format();
+        assert_eq!(
+            document.to_string(),
+            concat!(
+                "# comment\n",
+                "\n",
+                "export A='a b' #  note\r\n",
+                "B=plain\n",
+                "C=\"it's \\$5\"\n",
+                "D=\"${A}\"\n",
+                "E=onetwo\n",
+                "F=<<EOF\nbody\nEOF\n",
+                "G=\n",
+                "H='$HOME' # literal\n",
+                "I=\"l1\\nl2\"\n",
+                "J=\"x\\r\"\n",
+                "K=\"\\u00e9\\x07\"\n",
+                "L='tab\there'",
+            )
+        );
+        assert_eq!(resolved(&document.to_string()), before);
+
+        let formatted = document.to_string();
+        document.format();
+        assert_eq!(document.to_string(), formatted);
+    }
+
+    #[test]
+    fn test_sort_groups() {
+        let mut document = roundtrip("C=3\nA=1\nB=2\n\n# next\nZ=${Y}x\nY=y\n\nN=2\nM=1");
+        document.sort_groups();
+        assert_eq!(
+            document.to_string(),
+            "A=1\nB=2\nC=3\n\n# next\nZ=${Y}x\nY=y\n\nM=1\nN=2"
+        );
+
+        // duplicates keep their order
+        let mut document = roundtrip("B=2\nA=1\nB=3\n");
+        document.sort_groups();
+        assert_eq!(document.to_string(), "A=1\nB=2\nB=3\n");
+    }
+}
@@ -3,14 +3,26 @@ use std::error;
 use std::fmt;
 use std::io;
 
+use crate::check::EnvDiff;
+use crate::parse::KeyPolicy;
+use crate::report::Duplicate;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum Error {
-    LineParse(String, usize),
+    Parse(ParseError),
     Io(io::Error),
     EnvVar(env::VarError),
+    Value(ValueError),
+    Substitution(SubstitutionError),
+    /// The variables of a cycle of substitutions, starting and ending with the same one.
+    CyclicSubstitution(Vec<String>),
+    Check(EnvDiff),
+    DuplicateKey(Duplicate),
+    #[cfg(feature = "serde")]
+    Deserialize(crate::de::DeserializeError),
 }
 
 impl Error {
@@ -21,6 +33,14 @@ impl Error {
         }
         false
     }
+
+    /// Removes any variable value from the error, so it can be logged safely.
+    pub fn redact(self) -> Self {
+        match self {
+            Error::Value(err) => Error::Value(err.redact()),
+            err => err,
+        }
+    }
 }
 
 impl error::Error for Error {
@@ -28,6 +48,7 @@ impl error::Error for Error {
         match self {
             Error::Io(err) => Some(err),
             Error::EnvVar(err) => Some(err),
+            Error::Value(err) => err.source(),
             _ => None,
         }
     }
@@ -38,12 +59,383 @@ impl fmt::Display for Error {
         match self {
             Error::Io(err) => write!(fmt, "{}", err),
             Error::EnvVar(err) => write!(fmt, "{}", err),
-            Error::LineParse(line, error_index) => write!(
+            Error::Value(err) => write!(fmt, "{}", err),
+            Error::Substitution(err) => write!(fmt, "{}", err),
+            Error::CyclicSubstitution(cycle) => {
+                write!(fmt, "cyclic substitution: {}", cycle.join(" -> "))
+            }
+            Error::Check(diff) => write!(fmt, "{}", diff),
+            Error::DuplicateKey(duplicate) => write!(fmt, "{}", duplicate),
+            #[cfg(feature = "serde")]
+            Error::Deserialize(err) => write!(fmt, "{}", err),
+            Error::Parse(err) => write!(fmt, "{}", err),
+        }
+    }
+}
+
+/// The reason an environment variable could not be read by a typed getter such as
+/// [`var_parse`](crate::var_parse).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValueErrorKind {
+    /// The variable is not set.
+    NotPresent,
+    /// The variable is not valid unicode.
+    NotUnicode,
+    /// The value could not be parsed into the requested type.
+    Invalid,
+}
+
+/// An environment variable that could not be read as the requested type.
+///
+/// The error carries the name of the variable and, unless it has been
+/// [redacted](ValueError::redact), its offending value. For
+/// [`ValueErrorKind::Invalid`], the parse error is available as the
+/// [`source`](error::Error::source).
+#[derive(Debug)]
+pub struct ValueError {
+    key: String,
+    value: Option<String>,
+    kind: ValueErrorKind,
+    source: Option<Box<dyn error::Error + Send + Sync>>,
+}
+
+impl ValueError {
+    pub(crate) fn new(
+        key: String,
+        value: Option<String>,
+        kind: ValueErrorKind,
+        source: Option<Box<dyn error::Error + Send + Sync>>,
+    ) -> Self {
+        ValueError {
+            key,
+            value,
+            kind,
+            source,
+        }
+    }
+
+    /// The name of the variable.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The offending value, unless it was missing or has been redacted.
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// Why the variable could not be read.
+    pub fn kind(&self) -> ValueErrorKind {
+        self.kind
+    }
+
+    /// Removes the value from the error, so it can be logged safely.
+    pub fn redact(mut self) -> Self {
+        self.value = None;
+        self
+    }
+}
+
+impl error::Error for ValueError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.source {
+            Some(err) => Some(err.as_ref()),
+            None => None,
+        }
+    }
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ValueErrorKind::NotPresent => {
+                return write!(fmt, "environment variable `{}` not found", self.key)
+            }
+            ValueErrorKind::NotUnicode => write!(
+                fmt,
+                "environment variable `{}` is not valid unicode",
+                self.key
+            )?,
+            ValueErrorKind::Invalid => write!(
+                fmt,
+                "environment variable `{}` has an invalid value",
+                self.key
+            )?,
+        }
+        if let Some(value) = &self.value {
+            write!(fmt, " `{}`", value)?;
+        }
+        if let Some(source) = &self.source {
+            write!(fmt, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+/// What makes a line fail to parse.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ParseErrorKind {
+    /// A quote is never closed.
+    UnterminatedQuote,
+    /// A `${` is never closed.
+    UnterminatedSubstitution,
+    /// A heredoc is never closed by its delimiter.
+    UnterminatedHeredoc,
+    /// An escape sequence is invalid.
+    InvalidEscape,
+    /// The key is not accepted by the active [`KeyPolicy`].
+    InvalidKey(InvalidKeyError),
+    /// A line starts with `=`.
+    MissingKey,
+    /// The key is not followed by `=`.
+    MissingEquals,
+    /// An unquoted value is followed by something other than a comment.
+    TrailingCharacters,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnterminatedQuote => write!(fmt, "unterminated quote"),
+            ParseErrorKind::UnterminatedSubstitution => write!(fmt, "unterminated `${{`"),
+            ParseErrorKind::UnterminatedHeredoc => write!(fmt, "unterminated heredoc"),
+            ParseErrorKind::InvalidEscape => write!(fmt, "invalid escape sequence"),
+            ParseErrorKind::InvalidKey(err) => write!(fmt, "{}", err),
+            ParseErrorKind::MissingKey => write!(fmt, "expected a key before `=`"),
+            ParseErrorKind::MissingEquals => write!(fmt, "expected `=` after the key"),
+            ParseErrorKind::TrailingCharacters => {
+                write!(fmt, "unexpected characters after the value")
+            }
+        }
+    }
+}
+
+/// A line that could not be parsed, along with where it was found.
+///
+/// Displays as a snippet of the offending line with a caret under the problem:
+///
+/// ```text
+/// unterminated quote
+///  --> .env:3:8
+///   |
+/// 3 | SECRET="hunter2
+///   |        ^
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    kind: ParseErrorKind,
+    source_name: Option<String>,
+    line: usize,
+    column: usize,
+    start_line: usize,
+    line_text: String,
+    /// The byte offset of the problem in the logical line, until located.
+    offset: usize,
+}
+
+impl ParseError {
+    /// Creates an error at the byte `offset` of the value or line being parsed.
+    pub(crate) fn new(kind: ParseErrorKind, offset: usize) -> Self {
+        ParseError {
+            kind,
+            source_name: None,
+            line: 0,
+            column: 0,
+            start_line: 0,
+            line_text: String::new(),
+            offset,
+        }
+    }
+
+    /// Moves the offset by `by` bytes, once the error is known to be inside a
+    /// larger piece of text.
+    pub(crate) fn shift(mut self, by: usize) -> Self {
+        self.offset += by;
+        self
+    }
+
+    /// Finds the line and column of the offset in `text`, a logical line
+    /// starting on the 1-based line `start_line`.
+    pub(crate) fn locate(&mut self, text: &str, start_line: usize) {
+        let offset = (0..=self.offset.min(text.len()))
+            .rev()
+            .find(|&offset| text.is_char_boundary(offset))
+            .unwrap_or_default();
+        let line_start = text[..offset].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = text[offset..]
+            .find('\n')
+            .map_or(text.len(), |index| offset + index);
+
+        self.start_line = start_line;
+        self.line = start_line + text[..line_start].matches('\n').count();
+        self.column = text[line_start..offset].chars().count() + 1;
+        self.line_text = text[line_start..line_end].trim_end_matches('\r').to_owned();
+        self.offset = offset - line_start;
+    }
+
+    pub(crate) fn set_source_name(&mut self, name: Option<&str>) {
+        if self.source_name.is_none() {
+            self.source_name = name.map(str::to_owned);
+        }
+    }
+
+    /// What makes the line fail to parse.
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+
+    /// The name of the file or other source the line was read from, if known.
+    pub fn source_name(&self) -> Option<&str> {
+        self.source_name.as_deref()
+    }
+
+    /// The 1-based line of the problem.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The 1-based column of the problem, counted in characters.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// The 1-based line the definition starts on, which differs from
+    /// [`line`](ParseError::line) inside multi-line values.
+    pub fn start_line(&self) -> usize {
+        self.start_line
+    }
+
+    /// The text of the line holding the problem.
+    pub fn line_text(&self) -> &str {
+        &self.line_text
+    }
+}
+
+impl error::Error for ParseError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        // keep tabs so that the caret lines up with the text
+        let padding: String = self
+            .line_text
+            .get(..self.offset)
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        writeln!(fmt, "{}", self.kind)?;
+        match &self.source_name {
+            Some(name) => writeln!(fmt, "{}--> {}:{}:{}", gutter, name, self.line, self.column)?,
+            None => writeln!(fmt, "{}--> {}:{}", gutter, self.line, self.column)?,
+        }
+        writeln!(fmt, "{} |", gutter)?;
+        writeln!(fmt, "{} | {}", number, self.line_text)?;
+        write!(fmt, "{} | {}^", gutter, padding)?;
+        if self.start_line != self.line {
+            write!(
                 fmt,
-                "Error parsing line: '{}', error at line index: {}",
-                line, error_index
-            ),
+                "\n{} = note: the definition starts on line {}",
+                gutter, self.start_line
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A key that is not accepted by the active [`KeyPolicy`].
+#[derive(Debug, Clone)]
+pub struct InvalidKeyError {
+    key: String,
+    policy: KeyPolicy,
+}
+
+impl InvalidKeyError {
+    pub(crate) fn new(key: String, policy: KeyPolicy) -> Self {
+        InvalidKeyError { key, policy }
+    }
+
+    /// The rejected key.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The policy the key was checked against.
+    pub fn policy(&self) -> KeyPolicy {
+        self.policy
+    }
+}
+
+impl error::Error for InvalidKeyError {}
+
+impl fmt::Display for InvalidKeyError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "invalid key `{}`: {}",
+            self.key,
+            self.policy.describe()
+        )
+    }
+}
+
+/// A variable required by a substitution such as `${NAME:?message}` is not set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubstitutionError {
+    var: String,
+    key: String,
+    line: usize,
+    message: Option<String>,
+}
+
+impl SubstitutionError {
+    pub(crate) fn new(var: String, key: String, line: usize, message: Option<String>) -> Self {
+        SubstitutionError {
+            var,
+            key,
+            line,
+            message,
+        }
+    }
+
+    /// The name of the variable that is not set.
+    pub fn var(&self) -> &str {
+        &self.var
+    }
+
+    /// The key whose value refers to the variable.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The 1-based line the key is defined on.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The message given in `${NAME:?message}`, if any.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+impl error::Error for SubstitutionError {}
+
+impl fmt::Display for SubstitutionError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "variable `{}` is not set, referenced by `{}` on line {}",
+            self.var, self.key, self.line
+        )?;
+        if let Some(message) = &self.message {
+            write!(fmt, ": {}", message)?;
         }
+        Ok(())
     }
 }
 
@@ -73,9 +465,15 @@ mod test {
         assert_eq!(&env::VarError::NotPresent, var_err);
     }
 
+    fn parse_error(kind: ParseErrorKind, text: &str, offset: usize, start_line: usize) -> Error {
+        let mut err = ParseError::new(kind, offset);
+        err.locate(text, start_line);
+        Error::Parse(err)
+    }
+
     #[test]
-    fn test_lineparse_error_source() {
-        let err = Error::LineParse("test line".to_string(), 2);
+    fn test_parse_error_source() {
+        let err = parse_error(ParseErrorKind::MissingEquals, "KEY", 3, 1);
         assert!(err.source().is_none());
     }
 
@@ -112,12 +510,98 @@ mod test {
     }
 
     #[test]
-    fn test_lineparse_error_display() {
-        let err = Error::LineParse("test line".to_string(), 2);
-        let err_desc = format!("{}", err);
+    fn test_value_error_source() {
+        let parse_err = "x".parse::<u16>().unwrap_err();
+        let err = Error::Value(ValueError::new(
+            "PORT".to_string(),
+            Some("x".to_string()),
+            ValueErrorKind::Invalid,
+            Some(Box::new(parse_err.clone())),
+        ));
+        let source = err
+            .source()
+            .unwrap()
+            .downcast_ref::<std::num::ParseIntError>()
+            .unwrap();
+        assert_eq!(&parse_err, source);
+    }
+
+    #[test]
+    fn test_value_error_display() {
+        let err = Error::Value(ValueError::new(
+            "PORT".to_string(),
+            Some("x".to_string()),
+            ValueErrorKind::Invalid,
+            Some("bad digit".into()),
+        ));
+        assert_eq!(
+            "environment variable `PORT` has an invalid value `x`: bad digit",
+            format!("{}", err)
+        );
+        assert_eq!(
+            "environment variable `PORT` has an invalid value: bad digit",
+            format!("{}", err.redact())
+        );
+    }
+
+    #[test]
+    fn test_value_error_not_present_display() {
+        let err = ValueError::new("PORT".to_string(), None, ValueErrorKind::NotPresent, None);
+        assert_eq!("environment variable `PORT` not found", format!("{}", err));
+    }
+
+    #[test]
+    fn test_substitution_error_display() {
+        let err = Error::Substitution(SubstitutionError::new(
+            "DB_PASSWORD".to_string(),
+            "DATABASE_URL".to_string(),
+            3,
+            Some("set it in .env".to_string()),
+        ));
+        assert_eq!(
+            "variable `DB_PASSWORD` is not set, referenced by `DATABASE_URL` on line 3: set it in .env",
+            format!("{}", err)
+        );
+    }
+
+    #[test]
+    fn test_invalid_key_error_display() {
+        let err = ParseErrorKind::InvalidKey(InvalidKeyError::new(
+            "db.host".to_string(),
+            KeyPolicy::Posix,
+        ));
+        assert_eq!(
+            "invalid key `db.host`: POSIX keys must start with a letter or `_` and contain only ASCII letters, digits and `_`",
+            format!("{}", err)
+        );
+    }
+
+    #[test]
+    fn test_cyclic_substitution_error_display() {
+        let err = Error::CyclicSubstitution(vec!["A".into(), "B".into(), "A".into()]);
+        assert_eq!("cyclic substitution: A -> B -> A", format!("{}", err));
+    }
+
+    #[test]
+    fn test_parse_error_display() {
+        let err = parse_error(ParseErrorKind::TrailingCharacters, "KEY=a b", 6, 12);
+        assert_eq!(
+            "unexpected characters after the value\n  --> 12:7\n   |\n12 | KEY=a b\n   |       ^",
+            format!("{}", err)
+        );
+    }
+
+    #[test]
+    fn test_parse_error_display_multi_line() {
+        let mut err = ParseError::new(ParseErrorKind::InvalidEscape, 14);
+        err.locate("KEY=\"one\r\n\ttwo\\q\"", 2);
+        err.set_source_name(Some(".env"));
+
+        assert_eq!((err.line(), err.column(), err.start_line()), (3, 5, 2));
+        assert_eq!(err.line_text(), "\ttwo\\q\"");
         assert_eq!(
-            "Error parsing line: 'test line', error at line index: 2",
-            err_desc
+            "invalid escape sequence\n --> .env:3:5\n  |\n3 | \ttwo\\q\"\n  | \t   ^\n  = note: the definition starts on line 2",
+            format!("{}", err)
         );
     }
 }
@@ -23,8 +23,7 @@ impl<'a> Finder<'a> {
 
     pub fn find(self) -> Result<(PathBuf, Iter<File>)> {
         let path = find(&env::current_dir().map_err(Error::Io)?, self.filename)?;
-        let file = File::open(&path).map_err(Error::Io)?;
-        let iter = Iter::new(file);
+        let iter = Iter::from_path(&path)?;
         Ok((path, iter))
     }
 }
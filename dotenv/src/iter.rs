@@ -1,26 +1,253 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::env;
+use std::fs::File;
 use std::io::prelude::*;
-use std::io::BufReader;
+use std::io::{self, BufReader};
+use std::path::Path;
 
+use crate::deps::{self, Pending};
 use crate::errors::*;
-use crate::parse;
+use crate::loader::Precedence;
+use crate::map::EnvMap;
+use crate::parse::{self, KeyPolicy, Substitution, UndefinedVars};
+use crate::report::Unresolved;
+use crate::resolver::{ProcessEnv, Resolver};
 
-pub struct Iter<R> {
+pub struct Iter<R, V = ProcessEnv> {
     lines: QuotedLines<BufReader<R>>,
-    substitution_data: HashMap<String, Option<String>>,
+    substitution: Substitution,
+    resolver: V,
+    key_policy: KeyPolicy,
+    /// The name of the file or other source, for errors.
+    source_name: Option<String>,
+    forward_references: bool,
+    lenient: bool,
+    /// The definitions resolved ahead, with forward references.
+    pending: Option<VecDeque<Result<(usize, parse::Entry)>>>,
 }
 
 impl<R: Read> Iter<R> {
     pub fn new(reader: R) -> Iter<R> {
         Iter {
-            lines: QuotedLines {
-                buf: BufReader::new(reader),
-            },
-            substitution_data: HashMap::new(),
+            lines: QuotedLines::new(BufReader::new(reader)),
+            substitution: Substitution::default(),
+            resolver: ProcessEnv,
+            key_policy: KeyPolicy::default(),
+            source_name: None,
+            forward_references: false,
+            lenient: false,
+            pending: None,
+        }
+    }
+}
+
+impl Iter<File> {
+    /// Opens the file at `path`, naming it in parse errors.
+    pub(crate) fn from_path(path: &Path) -> Result<Iter<File>> {
+        let file = File::open(path).map_err(Error::Io)?;
+        Ok(Iter::new(file).source_name(path.display().to_string()))
+    }
+}
+
+impl<R: Read, V: Resolver> Iter<R, V> {
+    /// Sets the name of the file or other source being read, which is shown
+    /// in [`ParseError`]s.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dotenvy::{Error, Iter};
+    ///
+    /// let mut iter = Iter::new("PORT 8080".as_bytes()).source_name("config.env");
+    /// match iter.next() {
+    ///     Some(Err(Error::Parse(err))) => {
+    ///         assert_eq!(err.source_name(), Some("config.env"));
+    ///         assert_eq!((err.line(), err.column()), (1, 6));
+    ///     }
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn source_name(mut self, name: impl Into<String>) -> Self {
+        self.source_name = Some(name.into());
+        self
+    }
+
+    /// Sets where substitutions look up variables that are not defined earlier
+    /// in the reader's data, instead of the process environment.
+    ///
+    /// See [`Resolver`] for an example.
+    pub fn resolver<W: Resolver>(self, resolver: W) -> Iter<R, W> {
+        Iter {
+            lines: self.lines,
+            substitution: self.substitution,
+            resolver,
+            key_policy: self.key_policy,
+            source_name: self.source_name,
+            forward_references: self.forward_references,
+            lenient: self.lenient,
+            pending: self.pending,
         }
     }
 
+    /// Encodes the characters yielded by escapes like [`OsIter`](crate::OsIter)
+    /// encodes its input.
+    pub(crate) fn raw_bytes(mut self) -> Self {
+        self.substitution.raw_bytes = true;
+        self
+    }
+
+    /// Sets which keys are accepted, failing with [`ParseErrorKind::InvalidKey`]
+    /// on the others.
+    ///
+    /// Defaults to [`KeyPolicy::Default`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dotenvy::{Error, Iter, KeyPolicy, ParseErrorKind};
+    ///
+    /// let map = Iter::new("app.name=demo\nREDIS-URL=redis://cache".as_bytes())
+    ///     .key_policy(KeyPolicy::Permissive)
+    ///     .into_map()?;
+    /// assert_eq!(map.get("REDIS-URL"), Some("redis://cache"));
+    ///
+    /// let mut iter = Iter::new("app.name=demo".as_bytes()).key_policy(KeyPolicy::Posix);
+    /// match iter.next() {
+    ///     Some(Err(Error::Parse(err))) => {
+    ///         assert!(matches!(err.kind(), ParseErrorKind::InvalidKey(_)))
+    ///     }
+    ///     _ => unreachable!(),
+    /// }
+    /// # Ok::<(), dotenvy::Error>(())
+    /// ```
+    pub fn key_policy(mut self, key_policy: KeyPolicy) -> Self {
+        self.key_policy = key_policy;
+        self
+    }
+
+    /// Disables substitution, so that `$` is taken literally.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dotenvy::Iter;
+    ///
+    /// let map = Iter::new("PRICE=$5\nPATTERN=^[a-z]+$".as_bytes())
+    ///     .no_substitution()
+    ///     .into_map()?;
+    /// assert_eq!(map.get("PRICE"), Some("$5"));
+    /// assert_eq!(map.get("PATTERN"), Some("^[a-z]+$"));
+    /// # Ok::<(), dotenvy::Error>(())
+    /// ```
+    pub fn no_substitution(mut self) -> Self {
+        self.substitution.disabled = true;
+        self
+    }
+
+    /// Allows values to refer to variables defined further down.
+    ///
+    /// The whole reader is parsed before the first variable is returned, and
+    /// values are substituted in the order of their dependencies. A variable
+    /// defined more than once refers to its closest definition above, if any.
+    /// Definitions that refer to each other in a cycle fail with
+    /// [`Error::CyclicSubstitution`]. References in the word of an operator
+    /// count even when the word is not used, so `A=${C:-$B}` and `B=$A` form a
+    /// cycle even if `C` is set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dotenvy::Iter;
+    ///
+    /// let map = Iter::new("URL=http://${HOST}:8080\nHOST=localhost".as_bytes())
+    ///     .forward_references()
+    ///     .into_map()?;
+    /// assert_eq!(map.get("URL"), Some("http://localhost:8080"));
+    /// # Ok::<(), dotenvy::Error>(())
+    /// ```
+    pub fn forward_references(mut self) -> Self {
+        self.forward_references = true;
+        self
+    }
+
+    /// Recovers from lines that fail to parse, so that an error only affects
+    /// its own line.
+    ///
+    /// The iterator always moves on to the next line after an error. Without
+    /// this, a quote that is never closed fails every line up to the end of the
+    /// input, as it could be a multi-line value. With this, only the line
+    /// opening the quote fails, and the following lines are parsed on their own.
+    ///
+    /// [`load`](Iter::load) and [`into_map`](Iter::into_map) still stop at the
+    /// first error, see [`into_map_lenient`](Iter::into_map_lenient) and
+    /// [`EnvLoader::lenient`](crate::EnvLoader::lenient) to keep the valid lines.
+    pub fn lenient(mut self) -> Self {
+        self.lenient = true;
+        self
+    }
+
+    /// Sets how references to undefined variables are substituted.
+    ///
+    /// Defaults to [`UndefinedVars::Empty`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dotenvy::{Error, Iter, UndefinedVars};
+    ///
+    /// let mut iter = Iter::new("URL=postgres://${DB_USER}@localhost".as_bytes())
+    ///     .undefined_vars(UndefinedVars::Error);
+    ///
+    /// match iter.next() {
+    ///     Some(Err(Error::Substitution(err))) => assert_eq!(err.var(), "DB_USER"),
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn undefined_vars(mut self, undefined: UndefinedVars) -> Self {
+        self.substitution.undefined = undefined;
+        self
+    }
+
+    /// Sets whether substitutions prefer the existing environment or the
+    /// values defined earlier in the reader's data.
+    ///
+    /// By default, the environment wins, except when loading with
+    /// [`load_override`](Iter::load_override).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dotenvy::{Iter, Precedence};
+    ///
+    /// std::env::set_var("HOST", "env.example.com");
+    /// let map = Iter::new("HOST=file.example.com\nURL=https://$HOST".as_bytes())
+    ///     .substitution_precedence(Precedence::File)
+    ///     .into_map()?;
+    /// assert_eq!(map.get("URL"), Some("https://file.example.com"));
+    /// # Ok::<(), dotenvy::Error>(())
+    /// ```
+    pub fn substitution_precedence(mut self, precedence: Precedence) -> Self {
+        self.substitution.precedence = Some(precedence);
+        self
+    }
+
+    /// Sets the substitution precedence, unless it was set explicitly.
+    pub(crate) fn default_precedence(&mut self, precedence: Precedence) {
+        self.substitution.precedence.get_or_insert(precedence);
+    }
+
+    /// Returns the references to undefined variables found so far.
+    ///
+    /// These are only recorded with [`UndefinedVars::Collect`].
+    pub fn unresolved(&self) -> &[Unresolved] {
+        &self.substitution.unresolved
+    }
+
+    /// Takes the references to undefined variables found so far.
+    pub(crate) fn take_unresolved(&mut self) -> Vec<Unresolved> {
+        std::mem::take(&mut self.substitution.unresolved)
+    }
+
     /// Loads all variables found in the `reader` into the environment,
     /// preserving any existing environment variables of the same name.
     ///
@@ -28,10 +255,11 @@ impl<R: Read> Iter<R> {
     /// then the first occurrence is applied.
     pub fn load(mut self) -> Result<()> {
         self.remove_bom()?;
+        self.default_precedence(Precedence::Env);
 
         for item in self {
             let (key, value) = item?;
-            if env::var(&key).is_err() {
+            if env::var_os(&key).is_none() {
                 env::set_var(&key, value);
             }
         }
@@ -44,8 +272,13 @@ impl<R: Read> Iter<R> {
     ///
     /// If a variable is specified multiple times within the reader's data,
     /// then the last occurrence is applied.
+    ///
+    /// Substitutions also prefer the values defined earlier in the reader's data
+    /// to the existing environment, unless set otherwise with
+    /// [`substitution_precedence`](Iter::substitution_precedence).
     pub fn load_override(mut self) -> Result<()> {
         self.remove_bom()?;
+        self.default_precedence(Precedence::File);
 
         for item in self {
             let (key, value) = item?;
@@ -55,7 +288,69 @@ impl<R: Read> Iter<R> {
         Ok(())
     }
 
-    fn remove_bom(&mut self) -> Result<()> {
+    /// Collects all variables found in the `reader` into an [`EnvMap`]
+    /// without modifying the environment.
+    ///
+    /// If a variable is specified multiple times within the reader's data,
+    /// then the last occurrence is kept.
+    pub fn into_map(mut self) -> Result<EnvMap> {
+        self.remove_bom()?;
+
+        let mut map = EnvMap::new();
+        for item in self {
+            let (key, value) = item?;
+            map.insert(key, value);
+        }
+
+        Ok(map)
+    }
+
+    /// Collects every valid variable into an [`EnvMap`], along with the errors
+    /// of the lines that could not be parsed or substituted.
+    ///
+    /// This reads the input in [`lenient`](Iter::lenient) mode. Only I/O errors
+    /// stop reading, and are returned as such.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dotenvy::Iter;
+    ///
+    /// let (map, errors) = Iter::new("A=1\nB='oops\nC=3\nD 4\n".as_bytes()).into_map_lenient()?;
+    /// assert_eq!(map.get("A"), Some("1"));
+    /// assert_eq!(map.get("C"), Some("3"));
+    /// assert_eq!(errors.len(), 2);
+    /// # Ok::<(), dotenvy::Error>(())
+    /// ```
+    pub fn into_map_lenient(mut self) -> Result<(EnvMap, Vec<Error>)> {
+        self.remove_bom()?;
+        self.lenient = true;
+
+        let mut map = EnvMap::new();
+        let mut errors = Vec::new();
+        for item in self {
+            match item {
+                Ok((key, value)) => {
+                    map.insert(key, value);
+                }
+                Err(Error::Io(err)) => return Err(Error::Io(err)),
+                Err(err) => errors.push(err),
+            }
+        }
+
+        Ok((map, errors))
+    }
+
+    /// Seeds the values available to substitution before the first line is parsed.
+    pub(crate) fn with_substitution_data(
+        mut self,
+        substitution_data: HashMap<String, Option<String>>,
+    ) -> Self {
+        self.substitution.data = substitution_data;
+        self
+    }
+
+    pub(crate) fn remove_bom(&mut self) -> Result<()> {
         let buffer = self.lines.buf.fill_buf().map_err(Error::Io)?;
         // https://www.compart.com/en/unicode/U+FEFF
         if buffer.starts_with(&[0xEF, 0xBB, 0xBF]) {
@@ -66,15 +361,26 @@ impl<R: Read> Iter<R> {
     }
 }
 
-struct QuotedLines<B> {
+/// Splits its input into logical lines, joining the physical lines of
+/// multi-line values.
+pub(crate) struct QuotedLines<B> {
     buf: B,
+    /// The number of physical lines read so far.
+    pub(crate) line: usize,
+    /// The 1-based line the last logical line started on.
+    pub(crate) start_line: usize,
+    /// Lines to read again before reading on.
+    replay: String,
 }
 
 enum ParseState {
     Complete,
     Escape,
+    /// After a backslash at the end of an unquoted line.
+    Continuation,
     StrongOpen,
     StrongOpenEscape,
+    BacktickOpen,
     WeakOpen,
     WeakOpenEscape,
     Comment,
@@ -93,14 +399,22 @@ fn eval_end_state(prev_state: ParseState, buf: &str) -> (usize, ParseState) {
                 '\\' => ParseState::Escape,
                 '"' => ParseState::WeakOpen,
                 '\'' => ParseState::StrongOpen,
+                '`' => ParseState::BacktickOpen,
                 _ => ParseState::Complete,
             },
-            ParseState::Escape => ParseState::Complete,
-            ParseState::Complete => match c {
+            ParseState::Escape => match c {
+                '\n' => ParseState::Continuation,
+                '\r' => ParseState::Escape,
+                _ => ParseState::Complete,
+            },
+            // indentation on continuation lines is skipped
+            ParseState::Continuation if c == ' ' || c == '\t' => ParseState::Continuation,
+            ParseState::Complete | ParseState::Continuation => match c {
                 c if c.is_whitespace() && c != '\n' && c != '\r' => ParseState::WhiteSpace,
                 '\\' => ParseState::Escape,
                 '"' => ParseState::WeakOpen,
                 '\'' => ParseState::StrongOpen,
+                '`' => ParseState::BacktickOpen,
                 _ => ParseState::Complete,
             },
             ParseState::WeakOpen => match c {
@@ -115,6 +429,10 @@ fn eval_end_state(prev_state: ParseState, buf: &str) -> (usize, ParseState) {
                 _ => ParseState::StrongOpen,
             },
             ParseState::StrongOpenEscape => ParseState::StrongOpen,
+            ParseState::BacktickOpen => match c {
+                '`' => ParseState::Complete,
+                _ => ParseState::BacktickOpen,
+            },
             // Comments last the entire line.
             ParseState::Comment => panic!("should have returned early"),
         };
@@ -122,6 +440,64 @@ fn eval_end_state(prev_state: ParseState, buf: &str) -> (usize, ParseState) {
     (cur_pos, cur_state)
 }
 
+impl<B: BufRead> QuotedLines<B> {
+    pub(crate) fn new(buf: B) -> Self {
+        QuotedLines {
+            buf,
+            line: 0,
+            start_line: 0,
+            replay: String::new(),
+        }
+    }
+
+    /// Reads the next physical line, from the lines to replay first.
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        if self.replay.is_empty() {
+            return self.buf.read_line(buf);
+        }
+        let len = self
+            .replay
+            .find('\n')
+            .map_or(self.replay.len(), |index| index + 1);
+        buf.extend(self.replay.drain(..len));
+        Ok(len)
+    }
+
+    /// Reads the lines of `text` again, before the rest of the input.
+    fn replay(&mut self, text: &str) {
+        let mut text = text.to_owned();
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        self.line -= text.matches('\n').count();
+        self.replay.insert_str(0, &text);
+    }
+
+    /// Reads the body of a heredoc started by the line in `buf`, up to and
+    /// including its terminating line.
+    fn read_heredoc(&mut self, mut buf: String, heredoc: &parse::Heredoc) -> Result<String> {
+        loop {
+            let line_start = buf.len();
+            match self.read_line(&mut buf) {
+                Ok(0) => {
+                    let start = buf.find("<<").unwrap_or_default();
+                    let mut err = ParseError::new(ParseErrorKind::UnterminatedHeredoc, start);
+                    err.locate(&buf, self.start_line);
+                    return Err(Error::Parse(err));
+                }
+                Ok(_n) => {
+                    self.line += 1;
+                    if heredoc.is_terminator(&buf[line_start..]) {
+                        buf.truncate(buf.trim_end().len());
+                        return Ok(buf);
+                    }
+                }
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+    }
+}
+
 impl<B: BufRead> Iterator for QuotedLines<B> {
     type Item = Result<String>;
 
@@ -130,22 +506,27 @@ impl<B: BufRead> Iterator for QuotedLines<B> {
         let mut cur_state = ParseState::Complete;
         let mut buf_pos;
         let mut cur_pos;
+        self.start_line = self.line + 1;
         loop {
             buf_pos = buf.len();
-            match self.buf.read_line(&mut buf) {
+            match self.read_line(&mut buf) {
+                // the parser reports unterminated quotes and escapes
                 Ok(0) => match cur_state {
                     ParseState::Complete => return None,
-                    _ => {
-                        let len = buf.len();
-                        return Some(Err(Error::LineParse(buf, len)));
-                    }
+                    _ => return Some(Ok(buf)),
                 },
                 Ok(_n) => {
+                    self.line += 1;
                     // Skip lines which start with a # before iteration
                     // This optimizes parsing a bit.
                     if buf.trim_start().starts_with('#') {
                         return Some(Ok(String::with_capacity(0)));
                     }
+                    if buf_pos == 0 {
+                        if let Some(heredoc) = parse::heredoc(&buf) {
+                            return Some(self.read_heredoc(buf, &heredoc));
+                        }
+                    }
                     let result = eval_end_state(cur_state, &buf[buf_pos..]);
                     cur_pos = result.0;
                     cur_state = result.1;
@@ -161,8 +542,10 @@ impl<B: BufRead> Iterator for QuotedLines<B> {
                             return Some(Ok(buf));
                         }
                         ParseState::Escape
+                        | ParseState::Continuation
                         | ParseState::StrongOpen
                         | ParseState::StrongOpenEscape
+                        | ParseState::BacktickOpen
                         | ParseState::WeakOpen
                         | ParseState::WeakOpenEscape
                         | ParseState::WhiteSpace => {}
@@ -178,22 +561,135 @@ impl<B: BufRead> Iterator for QuotedLines<B> {
     }
 }
 
-impl<R: Read> Iterator for Iter<R> {
-    type Item = Result<(String, String)>;
+impl<R: Read, V: Resolver> Iter<R, V> {
+    /// Returns the next definition along with the line it starts on.
+    pub(crate) fn next_entry(&mut self) -> Option<Result<(usize, parse::Entry)>> {
+        if self.forward_references {
+            if self.pending.is_none() {
+                let lines = self.read_pending();
+                self.pending = Some(deps::expand_in_order(
+                    lines,
+                    &mut self.substitution,
+                    &self.resolver,
+                ));
+            }
+            return self.pending.as_mut().and_then(VecDeque::pop_front);
+        }
 
-    fn next(&mut self) -> Option<Self::Item> {
         loop {
             let line = match self.lines.next() {
                 Some(Ok(line)) => line,
-                Some(Err(err)) => return Some(Err(err)),
+                Some(Err(err)) => return Some(Err(self.locate(err, None))),
                 None => return None,
             };
 
-            match parse::parse_line(&line, &mut self.substitution_data) {
-                Ok(Some(result)) => return Some(Ok(result)),
+            match self.parse_recovering(&line, |iter, line| {
+                parse::parse_line(
+                    line,
+                    iter.lines.start_line,
+                    &mut iter.substitution,
+                    &iter.resolver,
+                    iter.key_policy,
+                )
+            }) {
+                Ok(Some(entry)) => return Some(Ok((self.lines.start_line, entry))),
                 Ok(None) => {}
-                Err(err) => return Some(Err(err)),
+                Err(err) => return Some(Err(self.locate(err, Some(&line)))),
             }
         }
     }
+
+    /// Parses `line`, parsing only its first line again if it fails in a way
+    /// that [`recover`](Iter::recover) handles.
+    fn parse_recovering<T>(
+        &mut self,
+        line: &str,
+        parse: impl Fn(&mut Self, &str) -> Result<T>,
+    ) -> Result<T> {
+        match parse(self, line) {
+            Err(err) => match self.recover(line, &err) {
+                Some(first) => parse(self, first),
+                None => Err(err),
+            },
+            result => result,
+        }
+    }
+
+    /// In lenient mode, splits a multi-line definition failing because of a
+    /// quote that is never closed, returning its first line and reading the
+    /// others again on their own.
+    ///
+    /// A quote is taken to be unclosed if the error says so, or if one of the
+    /// following lines looks like a definition.
+    fn recover<'l>(&mut self, line: &'l str, err: &Error) -> Option<&'l str> {
+        let kind = match err {
+            Error::Parse(err) if self.lenient => err.kind(),
+            _ => return None,
+        };
+        let (first, rest) = line.split_once('\n')?;
+        let unclosed = match kind {
+            ParseErrorKind::UnterminatedQuote | ParseErrorKind::TrailingCharacters => true,
+            _ => rest
+                .lines()
+                .any(|line| parse::is_definition(line, self.key_policy)),
+        };
+        if !unclosed {
+            return None;
+        }
+        self.lines.replay(rest);
+        Some(first.strip_suffix('\r').unwrap_or(first))
+    }
+
+    /// Adds where a parse error happened, `line` being the logical line it
+    /// happened in unless already known.
+    fn locate(&self, err: Error, line: Option<&str>) -> Error {
+        match err {
+            Error::Parse(mut err) => {
+                if let Some(line) = line {
+                    err.locate(line, self.lines.start_line);
+                }
+                err.set_source_name(self.source_name.as_deref());
+                Error::Parse(err)
+            }
+            err => err,
+        }
+    }
+
+    /// Parses every remaining line without substituting variables.
+    fn read_pending(&mut self) -> Vec<Pending> {
+        let mut lines = Vec::new();
+        loop {
+            let pending = match self.lines.next() {
+                Some(Ok(line)) => match self.parse_recovering(&line, |iter, line| {
+                    parse::parse_unexpanded(
+                        line,
+                        !iter.substitution.disabled,
+                        iter.substitution.raw_bytes,
+                        iter.key_policy,
+                    )
+                }) {
+                    Ok(Some(unexpanded)) => Pending::Parsed(self.lines.start_line, unexpanded),
+                    Ok(None) => continue,
+                    Err(err) => Pending::Failed(self.locate(err, Some(&line))),
+                },
+                Some(Err(Error::Io(err))) => {
+                    lines.push(Pending::Failed(Error::Io(err)));
+                    break;
+                }
+                Some(Err(err)) => Pending::Failed(self.locate(err, None)),
+                None => break,
+            };
+            lines.push(pending);
+        }
+        lines
+    }
+}
+
+impl<R: Read, V: Resolver> Iterator for Iter<R, V> {
+    type Item = Result<(String, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry()
+            .map(|result| result.map(|(_, entry)| (entry.key, entry.value)))
+    }
 }
@@ -2,22 +2,132 @@
 //! A well-maintained fork of the [`dotenv`] crate
 //!
 //! This library loads environment variables from a *.env* file. This is convenient for dev environments.
+//!
+//! # Escape sequences
+//!
+//! Inside double quotes, a backslash starts one of the following escape sequences:
+//!
+//! - `\\`, `\"`, `\'`, `\$` and `\ ` for the character itself
+//! - `\n`, `\r`, `\t`, `\a`, `\b`, `\f` and `\v` for the control characters
+//! - `\xHH` for the character U+00HH
+//! - `\uXXXX` and `\u{X...}` for a Unicode character, with surrogate pairs
+//!   written as two `\uXXXX` escapes
+//!
+//! Any other backslash is kept as it is, so `"C:\Users"` needs no escaping.
+//! Escapes for the NUL character are rejected, as environment variables cannot hold it.
+//!
+//! Unquoted values only accept `\n` and escaped quotes, backslashes, dollar signs
+//! and spaces. Single quoted and backtick quoted values take every character
+//! literally, so backticks allow both kinds of quotes inside without escaping.
+//! Variables are not substituted inside either of them.
+//!
+//! # Multi-line values
+//!
+//! Quoted values, including backtick quoted ones, may span several lines.
+//! Unquoted values continue on the next line after a trailing backslash,
+//! skipping the indentation of the next line.
+//!
+//! Values can also be written as a heredoc, ending at a line holding only the
+//! delimiter. The body is taken verbatim apart from substitution:
+//!
+//! ```text
+//! CERT=<<EOF
+//! -----BEGIN CERTIFICATE-----
+//! ...
+//! -----END CERTIFICATE-----
+//! EOF
+//! ```
+//!
+//! With `<<-EOF`, the indentation common to every line of the body is removed
+//! and the delimiter may be indented. With a quoted delimiter, as in `<<'EOF'`,
+//! variables are not substituted. Otherwise `\$` escapes a dollar sign and every
+//! other backslash is kept as it is.
+//!
+//! # Variable substitution
+//!
+//! Unquoted and double quoted values may refer to other variables as `$NAME` or
+//! `${NAME}`. Variables are looked up in the process environment first, and then
+//! in the lines above. The `*_override` functions prefer the lines above instead.
+//!
+//! An unbraced name ends at the first character that is not a letter or digit,
+//! so `$DB_HOST` refers to `DB` followed by the text `_HOST`. Errors and reports
+//! about undefined variables name `DB` as well, and `dotenvy lint` reports that
+//! `URL=$DB_HOST` refers to `DB`. Write `${DB_HOST}` to refer to `DB_HOST`.
+//!
+//! Inside braces, the following shell operators are supported:
+//!
+//! | Expression        | `NAME` set and not empty | `NAME` set but empty | `NAME` unset  |
+//! |-------------------|--------------------------|----------------------|---------------|
+//! | `${NAME:-word}`   | value                    | `word`               | `word`        |
+//! | `${NAME-word}`    | value                    | empty                | `word`        |
+//! | `${NAME:=word}`   | value                    | assign `word`        | assign `word` |
+//! | `${NAME=word}`    | value                    | empty                | assign `word` |
+//! | `${NAME:?msg}`    | value                    | error                | error         |
+//! | `${NAME?msg}`     | value                    | empty                | error         |
+//! | `${NAME:+word}`   | `word`                   | empty                | empty         |
+//! | `${NAME+word}`    | `word`                   | `word`               | empty         |
+//!
+//! `word` may itself contain substitutions. Assigning makes the value available
+//! to the lines below without defining a new variable, and an error is returned
+//! as [`Error::Substitution`].
+//!
+//! [`Iter`] and [`EnvLoader`] can change how substitution behaves:
+//!
+//! - [`undefined_vars`](Iter::undefined_vars) fails on or collects references
+//!   to undefined variables, which are otherwise replaced by the empty string
+//! - [`forward_references`](Iter::forward_references) allows referring to
+//!   variables defined further down
+//! - [`resolver`](Iter::resolver) looks up variables somewhere other than the
+//!   process environment
+//! - [`no_substitution`](Iter::no_substitution) takes `$` literally
+//!
+//! # Non-UTF-8 files
+//!
+//! [`Iter`] fails on bytes that are not valid UTF-8. [`OsIter`] yields
+//! [`OsString`]s instead and keeps such bytes unchanged on Unix, and
+//! [`var_os`] and [`vars_os`] read values that are not valid unicode.
 
+mod check;
+#[cfg(feature = "serde")]
+mod de;
+mod deps;
+mod document;
 mod errors;
 mod find;
 mod iter;
+mod lint;
+mod loader;
+mod map;
+mod os;
 mod parse;
+mod report;
+mod resolver;
+mod ser;
 
-use std::env::{self, Vars};
-use std::ffi::OsStr;
+use std::env::{self, Vars, VarsOs};
+use std::error;
+use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Once;
 
+pub use crate::check::{check, EnvDiff};
+#[cfg(feature = "serde")]
+pub use crate::de::{from_env, from_env_prefixed, from_map, DeserializeError, EnvDeserializer};
+pub use crate::document::{Document, Entry, Item, Quote, Trivia};
 pub use crate::errors::*;
 use crate::find::Finder;
 pub use crate::iter::Iter;
+pub use crate::lint::{lint, Diagnostic, Rule, Severity};
+pub use crate::loader::{DuplicateKeys, EnvLoader, EnvSource, Precedence};
+pub use crate::map::EnvMap;
+pub use crate::os::OsIter;
+pub use crate::parse::{KeyPolicy, UndefinedVars};
+pub use crate::report::{Definition, Duplicate, LoadReport, Origin, Status, Unresolved};
+pub use crate::resolver::{Chain, Isolated, ProcessEnv, Resolver};
+pub use crate::ser::{to_string, to_writer};
 
 static START: Once = Once::new();
 
@@ -39,7 +149,7 @@ static START: Once = Once::new();
 /// ```
 pub fn var<K: AsRef<OsStr>>(key: K) -> Result<String> {
     START.call_once(|| {
-        dotenv().ok();
+        dotenv_os().ok();
     });
     env::var(key).map_err(Error::EnvVar)
 }
@@ -56,11 +166,246 @@ pub fn var<K: AsRef<OsStr>>(key: K) -> Result<String> {
 /// ```
 pub fn vars() -> Vars {
     START.call_once(|| {
-        dotenv().ok();
+        dotenv_os().ok();
     });
     env::vars()
 }
 
+/// Gets the value for an environment variable, which need not be valid unicode.
+///
+/// Like [`var`], the *.env* file is loaded on first use. Values in the *.env* file
+/// that are not valid UTF-8 are loaded unchanged on Unix, see [`OsIter`].
+///
+/// # Examples:
+///
+/// ```no_run
+/// if let Some(path) = dotenvy::var_os("DATA_DIR") {
+///     println!("{}", path.to_string_lossy());
+/// }
+/// ```
+pub fn var_os<K: AsRef<OsStr>>(key: K) -> Option<OsString> {
+    START.call_once(|| {
+        dotenv_os().ok();
+    });
+    env::var_os(key)
+}
+
+/// Returns an iterator of `(key, value)` pairs for all environment variables of the
+/// current process, including those that are not valid unicode.
+///
+/// Like [`vars`], the *.env* file is loaded on first use and the iterator holds a
+/// snapshot of the environment.
+///
+/// # Examples:
+///
+/// ```no_run
+/// use std::ffi::OsString;
+///
+/// let result: Vec<(OsString, OsString)> = dotenvy::vars_os().collect();
+/// ```
+pub fn vars_os() -> VarsOs {
+    START.call_once(|| {
+        dotenv_os().ok();
+    });
+    env::vars_os()
+}
+
+/// Loads the *.env* file for the getters, keeping values that are not valid UTF-8.
+fn dotenv_os() -> Result<()> {
+    let path = find::find(&env::current_dir().map_err(Error::Io)?, Path::new(".env"))?;
+    OsIter::new(File::open(&path).map_err(Error::Io)?)
+        .source_name(path.display().to_string())
+        .load()
+}
+
+/// Gets the value for an environment variable and parses it into `T`.
+///
+/// Like [`var`], the *.env* file is loaded on first use. Unlike [`var`], the returned
+/// [`Error::Value`] names the variable, holds the offending value and exposes the
+/// parse error as its [`source`](std::error::Error::source).
+///
+/// # Examples:
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let port: u16 = dotenvy::var_parse("PORT")?;
+/// #     Ok(())
+/// # }
+/// ```
+pub fn var_parse<T, K>(key: K) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Into<Box<dyn error::Error + Send + Sync>>,
+    K: AsRef<OsStr>,
+{
+    let key = key.as_ref();
+    match lookup(key)? {
+        Some(value) => parse_value(key, value),
+        None => Err(value_error(key, None, ValueErrorKind::NotPresent, None)),
+    }
+}
+
+/// Gets the value for an environment variable and parses it into `T`,
+/// returning `None` if it is not set.
+///
+/// # Examples:
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let timeout: Option<u64> = dotenvy::var_opt("TIMEOUT_SECS")?;
+/// #     Ok(())
+/// # }
+/// ```
+pub fn var_opt<T, K>(key: K) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Into<Box<dyn error::Error + Send + Sync>>,
+    K: AsRef<OsStr>,
+{
+    let key = key.as_ref();
+    lookup(key)?
+        .map(|value| parse_value(key, value))
+        .transpose()
+}
+
+/// Gets the value for an environment variable and parses it into `T`,
+/// returning `default` if it is not set.
+///
+/// A value that is set but cannot be parsed is still an error.
+///
+/// # Examples:
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let workers = dotenvy::var_or("WORKERS", 4)?;
+/// #     Ok(())
+/// # }
+/// ```
+pub fn var_or<T, K>(key: K, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Into<Box<dyn error::Error + Send + Sync>>,
+    K: AsRef<OsStr>,
+{
+    Ok(var_opt(key)?.unwrap_or(default))
+}
+
+/// Gets the value for an environment variable as a `bool`.
+///
+/// `true`, `1`, `yes` and `on` are `true`; `false`, `0`, `no` and `off` are `false`.
+/// Case and surrounding whitespace are ignored.
+///
+/// # Examples:
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// if dotenvy::var_bool("DEBUG")? {
+///     println!("debugging enabled");
+/// }
+/// #     Ok(())
+/// # }
+/// ```
+pub fn var_bool<K: AsRef<OsStr>>(key: K) -> Result<bool> {
+    let key = key.as_ref();
+    let value = match lookup(key)? {
+        Some(value) => value,
+        None => return Err(value_error(key, None, ValueErrorKind::NotPresent, None)),
+    };
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Ok(true),
+        "false" | "0" | "no" | "off" => Ok(false),
+        _ => Err(value_error(
+            key,
+            Some(value),
+            ValueErrorKind::Invalid,
+            Some("expected one of true, false, 1, 0, yes, no, on or off".into()),
+        )),
+    }
+}
+
+/// Gets the value for an environment variable as a list, splitting it on `separator`
+/// and parsing each item into `T`.
+///
+/// Whitespace around items is trimmed and empty items are skipped,
+/// so an empty value is an empty list.
+///
+/// # Examples:
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let hosts: Vec<String> = dotenvy::var_list("ALLOWED_HOSTS", ',')?;
+/// #     Ok(())
+/// # }
+/// ```
+pub fn var_list<T, K>(key: K, separator: char) -> Result<Vec<T>>
+where
+    T: FromStr,
+    T::Err: Into<Box<dyn error::Error + Send + Sync>>,
+    K: AsRef<OsStr>,
+{
+    let key = key.as_ref();
+    let value = match lookup(key)? {
+        Some(value) => value,
+        None => return Err(value_error(key, None, ValueErrorKind::NotPresent, None)),
+    };
+    value
+        .split(separator)
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            item.parse().map_err(|err: T::Err| {
+                value_error(
+                    key,
+                    Some(value.clone()),
+                    ValueErrorKind::Invalid,
+                    Some(err.into()),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Loads the *.env* file on first use, then gets `key`, treating a missing variable as `None`.
+fn lookup(key: &OsStr) -> Result<Option<String>> {
+    START.call_once(|| {
+        dotenv_os().ok();
+    });
+    match env::var(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(value)) => Err(value_error(
+            key,
+            Some(value.to_string_lossy().into_owned()),
+            ValueErrorKind::NotUnicode,
+            None,
+        )),
+    }
+}
+
+fn parse_value<T>(key: &OsStr, value: String) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Into<Box<dyn error::Error + Send + Sync>>,
+{
+    value.parse().map_err(|err: T::Err| {
+        value_error(key, Some(value), ValueErrorKind::Invalid, Some(err.into()))
+    })
+}
+
+fn value_error(
+    key: &OsStr,
+    value: Option<String>,
+    kind: ValueErrorKind,
+    source: Option<Box<dyn error::Error + Send + Sync>>,
+) -> Error {
+    Error::Value(ValueError::new(
+        key.to_string_lossy().into_owned(),
+        value,
+        kind,
+        source,
+    ))
+}
+
 /// Loads environment variables from the specified path.
 ///
 /// If variables with the same names already exist in the environment, then their values will be
@@ -83,7 +428,7 @@ pub fn vars() -> Vars {
 /// # }
 /// ```
 pub fn from_path<P: AsRef<Path>>(path: P) -> Result<()> {
-    let iter = Iter::new(File::open(path).map_err(Error::Io)?);
+    let iter = Iter::from_path(path.as_ref())?;
     iter.load()
 }
 
@@ -108,7 +453,7 @@ pub fn from_path<P: AsRef<Path>>(path: P) -> Result<()> {
 /// # }
 /// ```
 pub fn from_path_override<P: AsRef<Path>>(path: P) -> Result<()> {
-    let iter = Iter::new(File::open(path).map_err(Error::Io)?);
+    let iter = Iter::from_path(path.as_ref())?;
     iter.load_override()
 }
 
@@ -128,7 +473,28 @@ pub fn from_path_override<P: AsRef<Path>>(path: P) -> Result<()> {
 /// # }
 /// ```
 pub fn from_path_iter<P: AsRef<Path>>(path: P) -> Result<Iter<File>> {
-    Ok(Iter::new(File::open(path).map_err(Error::Io)?))
+    Iter::from_path(path.as_ref())
+}
+
+/// Parses environment variables from the specified path into an [`EnvMap`],
+/// without modifying the environment.
+///
+/// Where multiple declarations for the same environment variable exist in your *.env*
+/// file, the *last one* is kept.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::path::Path;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let map = dotenvy::from_path_map(Path::new("path/to/.env"))?;
+/// map.load();
+/// #     Ok(())
+/// # }
+/// ```
+pub fn from_path_map<P: AsRef<Path>>(path: P) -> Result<EnvMap> {
+    Iter::from_path(path.as_ref())?.into_map()
 }
 
 /// Loads environment variables from the specified file.
@@ -209,12 +575,33 @@ pub fn from_filename_override<P: AsRef<Path>>(filename: P) -> Result<PathBuf> {
 /// #     Ok(())
 /// # }
 /// ```
-
 pub fn from_filename_iter<P: AsRef<Path>>(filename: P) -> Result<Iter<File>> {
     let (_, iter) = Finder::new().filename(filename.as_ref()).find()?;
     Ok(iter)
 }
 
+/// Parses environment variables from the specified file into an [`EnvMap`],
+/// without modifying the environment.
+///
+/// Where multiple declarations for the same environment variable exist in your *.env*
+/// file, the *last one* is kept.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let map = dotenvy::from_filename_map("custom.env")?;
+/// for (key, val) in map.iter() {
+///     println!("{}={}", key, val);
+/// }
+/// #     Ok(())
+/// # }
+/// ```
+pub fn from_filename_map<P: AsRef<Path>>(filename: P) -> Result<EnvMap> {
+    let (_, iter) = Finder::new().filename(filename.as_ref()).find()?;
+    iter.into_map()
+}
+
 /// Loads environment variables from [`io::Read`].
 ///
 /// This is useful for loading environment variables from IPC or the network.
@@ -304,6 +691,25 @@ pub fn from_read_iter<R: io::Read>(reader: R) -> Iter<R> {
     Iter::new(reader)
 }
 
+/// Parses environment variables from [`io::Read`] into an [`EnvMap`],
+/// without modifying the environment.
+///
+/// Where multiple declarations for the same environment variable exist in your `reader`,
+/// the *last one* is kept.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let map = dotenvy::from_read_map("KEY=value".as_bytes())?;
+/// assert_eq!(map.get("KEY"), Some("value"));
+/// #     Ok(())
+/// # }
+/// ```
+pub fn from_read_map<R: io::Read>(reader: R) -> Result<EnvMap> {
+    Iter::new(reader).into_map()
+}
+
 /// Loads the *.env* file from the current directory or parents. This is typically what you want.
 ///
 /// If variables with the same names already exist in the environment, then their values will be
@@ -346,7 +752,6 @@ pub fn dotenv() -> Result<PathBuf> {
 /// #     Ok(())
 /// # }
 /// ```
-
 pub fn dotenv_optional() -> Result<Option<PathBuf>> {
     match Finder::new().find() {
         Ok((path, iter)) => {
@@ -403,3 +808,56 @@ pub fn dotenv_iter() -> Result<iter::Iter<File>> {
     let (_, iter) = Finder::new().find()?;
     Ok(iter)
 }
+
+/// Parses the *.env* file from the current directory or parents into an [`EnvMap`],
+/// without modifying the environment.
+///
+/// Where multiple declarations for the same environment variable exist in your *.env*
+/// file, the *last one* is kept.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let map = dotenvy::dotenv_map()?;
+/// map.load_override();
+/// #     Ok(())
+/// # }
+/// ```
+pub fn dotenv_map() -> Result<EnvMap> {
+    let (_, iter) = Finder::new().find()?;
+    iter.into_map()
+}
+
+/// Checks the environment against a reference file such as *.env.example*.
+///
+/// The reference is searched for in the current directory and its parents, like
+/// [`from_filename`]. Every variable it declares must be set to a non-empty value,
+/// either in the process environment or in the *.env* file. Variables in the *.env*
+/// file that the reference does not declare are reported too.
+///
+/// The environment is not modified. A missing *.env* file is treated as empty.
+/// Values in the reference are read without substitution, as only its keys matter.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// if let Err(dotenvy::Error::Check(diff)) = dotenvy::check_against(".env.example") {
+///     for key in diff.missing() {
+///         eprintln!("{} is not set", key);
+///     }
+/// }
+/// #     Ok(())
+/// # }
+/// ```
+pub fn check_against<P: AsRef<Path>>(reference: P) -> Result<()> {
+    let (_, iter) = Finder::new().filename(reference.as_ref()).find()?;
+    let reference = iter.no_substitution().into_map()?;
+    let dotenv = match dotenv_map() {
+        Ok(map) => map,
+        Err(e) if e.not_found() => EnvMap::new(),
+        Err(e) => return Err(e),
+    };
+    check(&reference, &dotenv)
+}
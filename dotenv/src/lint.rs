@@ -0,0 +1,514 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::document::{self, Entry, Item, Items, Quote, ANY_KEY};
+use crate::errors::*;
+use crate::parse;
+
+/// The comment disabling rules for the line below it.
+const DISABLE_NEXT_LINE: &str = "dotenvy-lint-disable-next-line";
+
+/// A check made by [`lint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Rule {
+    /// `parse-error`: a line that cannot be parsed.
+    ParseError,
+    /// `duplicate-key`: a key defined more than once.
+    DuplicateKey,
+    /// `lowercase-key`: a key containing lowercase letters.
+    LowercaseKey,
+    /// `unquoted-whitespace`: an unquoted value containing whitespace, which
+    /// cannot be parsed.
+    UnquotedWhitespace,
+    /// `space-around-equals`: whitespace around the `=` of a definition.
+    SpaceAroundEquals,
+    /// `trailing-whitespace`: whitespace at the end of a line.
+    TrailingWhitespace,
+    /// `unordered-key`: a key sorting before the one above it, in a group of
+    /// definitions between blank or comment lines.
+    UnorderedKey,
+    /// `empty-value`: a definition without a value, such as `KEY=`.
+    EmptyValue,
+    /// `undefined-reference`: a substitution referring to a variable that is not
+    /// defined above, without an expansion operator providing a default.
+    UndefinedReference,
+    /// `shell-incompatible`: a value that a shell sourcing the file would read
+    /// differently.
+    ShellIncompatible,
+}
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// A likely mistake or a matter of style.
+    Warning,
+    /// A line that cannot be loaded.
+    Error,
+}
+
+/// A problem found by [`lint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    rule: Rule,
+    line: usize,
+    message: String,
+}
+
+impl Rule {
+    /// Every rule, in the order of their IDs.
+    pub const ALL: [Rule; 10] = [
+        Rule::DuplicateKey,
+        Rule::EmptyValue,
+        Rule::LowercaseKey,
+        Rule::ParseError,
+        Rule::ShellIncompatible,
+        Rule::SpaceAroundEquals,
+        Rule::TrailingWhitespace,
+        Rule::UndefinedReference,
+        Rule::UnorderedKey,
+        Rule::UnquotedWhitespace,
+    ];
+
+    /// The ID of the rule, as used in disable comments.
+    pub fn id(self) -> &'static str {
+        match self {
+            Rule::ParseError => "parse-error",
+            Rule::DuplicateKey => "duplicate-key",
+            Rule::LowercaseKey => "lowercase-key",
+            Rule::UnquotedWhitespace => "unquoted-whitespace",
+            Rule::SpaceAroundEquals => "space-around-equals",
+            Rule::TrailingWhitespace => "trailing-whitespace",
+            Rule::UnorderedKey => "unordered-key",
+            Rule::EmptyValue => "empty-value",
+            Rule::UndefinedReference => "undefined-reference",
+            Rule::ShellIncompatible => "shell-incompatible",
+        }
+    }
+
+    /// Returns the rule with the given ID.
+    pub fn from_id(id: &str) -> Option<Rule> {
+        Rule::ALL.iter().copied().find(|rule| rule.id() == id)
+    }
+
+    /// How serious the problems found by the rule are.
+    pub fn severity(self) -> Severity {
+        match self {
+            Rule::ParseError | Rule::UnquotedWhitespace => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.id())
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(fmt, "warning"),
+            Severity::Error => write!(fmt, "error"),
+        }
+    }
+}
+
+impl Diagnostic {
+    /// The rule that found the problem.
+    pub fn rule(&self) -> Rule {
+        self.rule
+    }
+
+    /// The severity of the rule.
+    pub fn severity(&self) -> Severity {
+        self.rule.severity()
+    }
+
+    /// The 1-based line of the problem.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Describes the problem.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}[{}]: {}", self.severity(), self.rule, self.message)
+    }
+}
+
+/// Checks the text of a *.env* file against every [`Rule`], returning the
+/// problems found in the order of the lines.
+///
+/// Lines that cannot be parsed are reported, and the lines after them are
+/// still checked. A comment such as
+/// `# dotenvy-lint-disable-next-line empty-value, unordered-key` disables the
+/// rules it lists for the line below it, or every rule if it lists none.
+///
+/// # Examples
+///
+/// ```
+/// use dotenvy::Rule;
+///
+/// let text = "PORT=8080\nhost=localhost\n# dotenvy-lint-disable-next-line\nPORT = 9090\n";
+/// let diagnostics = dotenvy::lint(text);
+///
+/// assert_eq!(diagnostics.len(), 1);
+/// assert_eq!(diagnostics[0].rule(), Rule::LowercaseKey);
+/// assert_eq!(diagnostics[0].line(), 2);
+/// assert_eq!(
+///     diagnostics[0].to_string(),
+///     "warning[lowercase-key]: `host` contains lowercase letters"
+/// );
+/// ```
+pub fn lint(text: &str) -> Vec<Diagnostic> {
+    let (_, text) = document::strip_bom(text);
+    let mut linter = Linter::default();
+    for (line, item) in Items::new(text, None) {
+        match item {
+            Ok(Item::Entry(entry)) => linter.entry(line, &entry),
+            Ok(Item::Blank(trivia)) | Ok(Item::Comment(trivia)) => {
+                linter.previous_key = None;
+                let text = trivia.text();
+                if text.ends_with(char::is_whitespace) {
+                    linter.report(
+                        line,
+                        Rule::TrailingWhitespace,
+                        "trailing whitespace".to_owned(),
+                    );
+                }
+                linter.disable(line, text);
+            }
+            Err(err) => linter.error(line, err),
+        }
+    }
+    linter.finish()
+}
+
+#[derive(Default)]
+struct Linter {
+    /// The diagnostics found, along with the line their definition starts on.
+    diagnostics: Vec<(usize, Diagnostic)>,
+    /// The line each key was last defined on.
+    defined: HashMap<String, usize>,
+    /// The key above in the current group of definitions.
+    previous_key: Option<String>,
+    /// The rules disabled for each line, all of them if empty.
+    disabled: HashMap<usize, Vec<Rule>>,
+}
+
+impl Linter {
+    fn report(&mut self, line: usize, rule: Rule, message: String) {
+        self.report_at(line, line, rule, message);
+    }
+
+    /// Reports a problem on `line` of the definition starting on `start_line`.
+    fn report_at(&mut self, start_line: usize, line: usize, rule: Rule, message: String) {
+        self.diagnostics.push((
+            start_line,
+            Diagnostic {
+                rule,
+                line,
+                message,
+            },
+        ));
+    }
+
+    /// Records the rules disabled by the comment `text` on `line`, if any.
+    fn disable(&mut self, line: usize, text: &str) {
+        let rules = match text.trim().strip_prefix('#').map(str::trim_start) {
+            Some(comment) => match comment.strip_prefix(DISABLE_NEXT_LINE) {
+                Some(rules) if rules.is_empty() || rules.starts_with(char::is_whitespace) => rules,
+                _ => return,
+            },
+            None => return,
+        };
+        let rules = rules
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter_map(Rule::from_id)
+            .collect();
+        self.disabled.insert(line + 1, rules);
+    }
+
+    fn error(&mut self, start_line: usize, err: Error) {
+        self.previous_key = None;
+        let err = match err {
+            Error::Parse(err) => err,
+            err => return self.report(start_line, Rule::ParseError, err.to_string()),
+        };
+
+        let value = err
+            .line_text()
+            .split_once('=')
+            .map_or("", |(_, value)| value.trim_start());
+        let (rule, message) = match err.kind() {
+            ParseErrorKind::TrailingCharacters
+                if err.line() == start_line && !value.starts_with(['\'', '"', '`']) =>
+            {
+                (
+                    Rule::UnquotedWhitespace,
+                    "unquoted value contains whitespace".to_owned(),
+                )
+            }
+            kind => (Rule::ParseError, kind.to_string()),
+        };
+        self.report_at(start_line, err.line(), rule, message);
+    }
+
+    fn entry(&mut self, line: usize, entry: &Entry) {
+        let key = entry.key();
+
+        if let Some(previous) = self.defined.get(key) {
+            let message = format!("`{}` is already defined on line {}", key, previous);
+            self.report(line, Rule::DuplicateKey, message);
+        }
+        if key.chars().any(char::is_lowercase) {
+            let message = format!("`{}` contains lowercase letters", key);
+            self.report(line, Rule::LowercaseKey, message);
+        }
+        if entry.separator != "=" {
+            let message = format!("whitespace around `=` in the definition of `{}`", key);
+            self.report(line, Rule::SpaceAroundEquals, message);
+        }
+        if entry.trailing.ends_with(char::is_whitespace) {
+            let last_line = line
+                + entry
+                    .to_string()
+                    .trim_end_matches('\n')
+                    .matches('\n')
+                    .count();
+            self.report_at(
+                line,
+                last_line,
+                Rule::TrailingWhitespace,
+                "trailing whitespace".to_owned(),
+            );
+        }
+        match &self.previous_key {
+            Some(previous) if key < previous.as_str() => {
+                let message = format!("`{}` should come before `{}`", key, previous);
+                self.report(line, Rule::UnorderedKey, message);
+            }
+            _ => {}
+        }
+        if entry.raw_value().is_empty() {
+            let message = format!("`{}` has no value", key);
+            self.report(line, Rule::EmptyValue, message);
+        }
+
+        let text = entry.to_string();
+        let text = text.trim_end_matches(entry.line_ending.as_str());
+        if let Ok(Some(unexpanded)) = parse::parse_unexpanded(text, true, false, ANY_KEY) {
+            let mut seen = HashSet::new();
+            for name in unexpanded.plain_references() {
+                if seen.insert(name) && !self.defined.contains_key(name) {
+                    let message =
+                        format!("`{}` refers to `{}`, which is not defined above", key, name);
+                    self.report(line, Rule::UndefinedReference, message);
+                }
+            }
+        }
+        if let Some(reason) = shell_difference(entry) {
+            let message = format!(
+                "the value of `{}` is read differently by shells: {}",
+                key, reason
+            );
+            self.report(line, Rule::ShellIncompatible, message);
+        }
+
+        self.defined.insert(key.to_owned(), line);
+        self.previous_key = Some(key.to_owned());
+    }
+
+    /// Returns the diagnostics that are not disabled.
+    fn finish(self) -> Vec<Diagnostic> {
+        let disabled = self.disabled;
+        self.diagnostics
+            .into_iter()
+            .filter(|(line, diagnostic)| match disabled.get(line) {
+                Some(rules) => !rules.is_empty() && !rules.contains(&diagnostic.rule),
+                None => true,
+            })
+            .map(|(_, diagnostic)| diagnostic)
+            .collect()
+    }
+}
+
+/// Returns why a shell such as bash would read the value of `entry`
+/// differently, if it would.
+fn shell_difference(entry: &Entry) -> Option<&'static str> {
+    let raw = entry.raw_value();
+    match entry.quote() {
+        Quote::Backtick => return Some("backticks run a command"),
+        Quote::Heredoc => return Some("heredocs are not values"),
+        Quote::Single => return None,
+        Quote::Double | Quote::Unquoted => {}
+    }
+    let double = entry.quote() == Quote::Double;
+
+    if raw.contains("$(") || raw.contains('`') {
+        return Some("`$(` and backticks run a command");
+    }
+    if !double {
+        if raw.starts_with('~') {
+            return Some("a leading `~` is expanded to the home directory");
+        }
+        if raw.contains([';', '&', '|', '<', '>', '(', ')']) {
+            return Some("`;`, `&`, `|`, `<`, `>`, `(` and `)` are special characters");
+        }
+    }
+
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            continue;
+        }
+        match chars.next() {
+            Some('n') if !double => return Some("`\\n` is read as `n` outside of quotes"),
+            Some('\'' | ' ' | 'n' | 'r' | 't' | 'a' | 'b' | 'f' | 'v' | 'x' | 'u' | '`')
+                if double =>
+            {
+                return Some("escapes such as `\\n` are kept as they are in double quotes")
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rules(text: &str) -> Vec<(usize, Rule)> {
+        lint(text)
+            .iter()
+            .map(|diagnostic| (diagnostic.line(), diagnostic.rule()))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let text = "# database\nDB_HOST=localhost\nDB_PORT=5432\n\nURL=\"postgres://${DB_HOST}:${DB_PORT}\"\nWELCOME='it is'\n";
+        assert_eq!(rules(text), vec![]);
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(
+            rules("A=1\nB=2\nA=3\n"),
+            vec![(3, Rule::DuplicateKey), (3, Rule::UnorderedKey)]
+        );
+        assert_eq!(rules("a_key=1"), vec![(1, Rule::LowercaseKey)]);
+        assert_eq!(
+            rules("A = 1\nB= 2\nC =3"),
+            vec![
+                (1, Rule::SpaceAroundEquals),
+                (2, Rule::SpaceAroundEquals),
+                (3, Rule::SpaceAroundEquals),
+            ]
+        );
+        assert_eq!(
+            rules("A=1 \n# c\t\n  \nB='x\ny' # c "),
+            vec![
+                (1, Rule::TrailingWhitespace),
+                (2, Rule::TrailingWhitespace),
+                (3, Rule::TrailingWhitespace),
+                (5, Rule::TrailingWhitespace),
+            ]
+        );
+        assert_eq!(
+            rules("B=1\nA=2\n\nC=3\n# c\nB=4"),
+            vec![(2, Rule::UnorderedKey), (6, Rule::DuplicateKey),]
+        );
+        assert_eq!(rules("A=\nB=''\nC=\"\""), vec![(1, Rule::EmptyValue)]);
+        assert_eq!(
+            rules("A=$B\nB=${C:-x}${D}\nE='$F'"),
+            vec![(1, Rule::UndefinedReference), (2, Rule::UndefinedReference)]
+        );
+    }
+
+    #[test]
+    fn test_undefined_reference_reported_once() {
+        let messages: Vec<_> = lint("B=$X$Y$X")
+            .iter()
+            .map(|diagnostic| diagnostic.message().to_owned())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "`B` refers to `X`, which is not defined above",
+                "`B` refers to `Y`, which is not defined above",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let diagnostics = lint("A=1\nB=two words\nC='open\nD=4");
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].rule(), Rule::UnquotedWhitespace);
+        assert_eq!(diagnostics[0].severity(), Severity::Error);
+        assert_eq!(diagnostics[0].line(), 2);
+        assert_eq!(diagnostics[1].rule(), Rule::ParseError);
+        assert_eq!(diagnostics[1].message(), "unterminated quote");
+        assert_eq!(diagnostics[1].line(), 3);
+
+        assert_eq!(
+            rules("B=\"two\" words\nNO_EQUALS\nC=3"),
+            vec![(1, Rule::ParseError), (2, Rule::ParseError)]
+        );
+    }
+
+    #[test]
+    fn test_shell_incompatible() {
+        let incompatible = [
+            "A=`date`",
+            "A=<<EOF\nx\nEOF",
+            "A=\"line\\nbreak\"",
+            "A=\"it\\'s\"",
+            "A=\"$(date)\"",
+            "A=a\\nb",
+            "A=~/bin",
+            "A=a;b",
+            "A=a|b",
+        ];
+        for text in incompatible.iter() {
+            assert_eq!(rules(text), vec![(1, Rule::ShellIncompatible)], "{}", text);
+        }
+
+        let compatible = [
+            "A='`date` \\n ~'",
+            "A=\"C:\\Users \\$HOME \\\" \\\\\"",
+            "A=a\\ b",
+            "A=a~b",
+            "A=\"a;b\"",
+        ];
+        for text in compatible.iter() {
+            assert_eq!(rules(text), vec![], "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_disable_comments() {
+        let text = "# dotenvy-lint-disable-next-line\nb=\n# dotenvy-lint-disable-next-line empty-value, unknown\nc=\n#dotenvy-lint-disable-next-lines\nD=\n";
+        assert_eq!(
+            rules(text),
+            vec![(4, Rule::LowercaseKey), (6, Rule::EmptyValue)]
+        );
+    }
+
+    #[test]
+    fn test_rule_ids() {
+        for rule in Rule::ALL.iter() {
+            assert_eq!(Rule::from_id(rule.id()), Some(*rule));
+        }
+        assert_eq!(Rule::from_id("unknown"), None);
+    }
+}
@@ -0,0 +1,557 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::errors::*;
+use crate::find;
+use crate::iter::Iter;
+use crate::map::EnvMap;
+use crate::parse::{KeyPolicy, UndefinedVars};
+use crate::report::{Definition, Duplicate, LoadReport, Origin, Status};
+use crate::resolver::{ProcessEnv, Resolver};
+
+/// Decides whether the process environment or the loaded sources win when a
+/// variable is defined in both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precedence {
+    /// Variables already present in the process environment are preserved.
+    ///
+    /// This is the behaviour of [`dotenv`](crate::dotenv).
+    #[default]
+    Env,
+    /// Variables from the loaded sources override the process environment.
+    ///
+    /// This is the behaviour of [`dotenv_override`](crate::dotenv_override).
+    File,
+}
+
+/// Decides what happens when the sources of an [`EnvLoader`] define the same
+/// variable more than once, whether in one source or across several.
+///
+/// This is independent of [`Precedence`], which decides whether the winning
+/// definition overrides the process environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateKeys {
+    /// The last definition wins, so that later sources override earlier ones.
+    #[default]
+    LastWins,
+    /// The first definition wins.
+    FirstWins,
+    /// The last definition wins, and every duplicate is listed by
+    /// [`LoadReport::duplicates`].
+    Warn,
+    /// Loading fails with [`Error::DuplicateKey`].
+    Error,
+}
+
+/// A single source of variables for an [`EnvLoader`].
+///
+/// Sources are required by default. A missing required file makes
+/// [`EnvLoader::load`] fail, whereas a missing [`optional`](EnvSource::optional)
+/// file is skipped.
+pub struct EnvSource<'a> {
+    kind: SourceKind<'a>,
+    optional: bool,
+    label: Option<String>,
+}
+
+type SourceIter<'a> = Iter<Box<dyn Read + 'a>>;
+
+enum SourceKind<'a> {
+    Path(PathBuf),
+    Filename(PathBuf),
+    Reader(Box<dyn Read + 'a>),
+}
+
+impl<'a> EnvSource<'a> {
+    /// A file at the exact `path`.
+    pub fn path<P: AsRef<Path>>(path: P) -> Self {
+        EnvSource::new(SourceKind::Path(path.as_ref().to_owned()))
+    }
+
+    /// A file searched for in the current directory and its parents, like
+    /// [`from_filename`](crate::from_filename).
+    pub fn filename<P: AsRef<Path>>(filename: P) -> Self {
+        EnvSource::new(SourceKind::Filename(filename.as_ref().to_owned()))
+    }
+
+    /// Any [`io::Read`], such as a socket or an in-memory buffer.
+    pub fn reader<R: Read + 'a>(reader: R) -> Self {
+        EnvSource::new(SourceKind::Reader(Box::new(reader)))
+    }
+
+    /// The contents of a *.env* file held in memory.
+    pub fn string<S: Into<String>>(contents: S) -> Self {
+        EnvSource::reader(io::Cursor::new(contents.into().into_bytes()))
+    }
+
+    /// Skip this source instead of failing if the file cannot be found.
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    /// Names a reader or string source in a [`LoadReport`].
+    ///
+    /// Files are always identified by their path. Unlabelled readers are named `<reader>`.
+    pub fn label<S: Into<String>>(mut self, label: S) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    fn new(kind: SourceKind<'a>) -> Self {
+        EnvSource {
+            kind,
+            optional: false,
+            label: None,
+        }
+    }
+
+    /// Opens the source, returning `None` if it is optional and not found.
+    fn open(self) -> Result<Option<(Origin, SourceIter<'a>)>> {
+        let label = self.label;
+        let opened: Result<(Origin, Box<dyn Read + 'a>)> = match self.kind {
+            SourceKind::Path(path) => File::open(&path)
+                .map(|file| (Origin::Path(path), Box::new(file) as Box<dyn Read>))
+                .map_err(Error::Io),
+            SourceKind::Filename(filename) => env::current_dir()
+                .map_err(Error::Io)
+                .and_then(|dir| find::find(&dir, &filename))
+                .and_then(|path| {
+                    File::open(&path)
+                        .map(|file| (Origin::Path(path), Box::new(file) as Box<dyn Read>))
+                        .map_err(Error::Io)
+                }),
+            SourceKind::Reader(reader) => Ok((
+                Origin::Reader(label.unwrap_or_else(|| String::from("<reader>"))),
+                reader,
+            )),
+        };
+
+        match opened {
+            Ok((origin, reader)) => {
+                let iter = Iter::new(reader).source_name(origin.to_string());
+                Ok(Some((origin, iter)))
+            }
+            Err(e) if self.optional && e.not_found() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Loads variables from several sources at once.
+///
+/// Sources are read in the order they were added. When the same variable is
+/// defined more than once, whether in one source or across several, the *last*
+/// definition wins unless set otherwise with
+/// [`duplicate_keys`](EnvLoader::duplicate_keys). Values may refer to variables
+/// defined by earlier sources.
+///
+/// Whether the result then overrides the existing process environment is
+/// controlled by [`precedence`](EnvLoader::precedence).
+///
+/// # Examples
+///
+/// ```no_run
+/// use dotenvy::{EnvLoader, EnvSource, Precedence};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// EnvLoader::new()
+///     .filename(".env")
+///     .source(EnvSource::filename(".env.local").optional())
+///     .string("LOG_LEVEL=debug")
+///     .precedence(Precedence::File)
+///     .load()?;
+/// #     Ok(())
+/// # }
+/// ```
+pub struct EnvLoader<'a> {
+    sources: Vec<EnvSource<'a>>,
+    precedence: Precedence,
+    substitution_precedence: Option<Precedence>,
+    undefined: UndefinedVars,
+    forward_references: bool,
+    resolver: Box<dyn Resolver + 'a>,
+    key_policy: KeyPolicy,
+    duplicate_keys: DuplicateKeys,
+    substitution: bool,
+    lenient: bool,
+}
+
+impl<'a> EnvLoader<'a> {
+    /// Creates a loader with no sources that preserves the existing environment.
+    pub fn new() -> Self {
+        EnvLoader {
+            sources: Vec::new(),
+            precedence: Precedence::default(),
+            substitution_precedence: None,
+            undefined: UndefinedVars::default(),
+            forward_references: false,
+            resolver: Box::new(ProcessEnv),
+            key_policy: KeyPolicy::default(),
+            duplicate_keys: DuplicateKeys::default(),
+            substitution: true,
+            lenient: false,
+        }
+    }
+
+    /// Adds a source after those already added.
+    pub fn source(mut self, source: EnvSource<'a>) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    /// Adds a required file at the exact `path`.
+    pub fn path<P: AsRef<Path>>(self, path: P) -> Self {
+        self.source(EnvSource::path(path))
+    }
+
+    /// Adds a required file searched for in the current directory and its parents.
+    pub fn filename<P: AsRef<Path>>(self, filename: P) -> Self {
+        self.source(EnvSource::filename(filename))
+    }
+
+    /// Adds a reader.
+    pub fn reader<R: Read + 'a>(self, reader: R) -> Self {
+        self.source(EnvSource::reader(reader))
+    }
+
+    /// Adds the contents of a *.env* file held in memory.
+    pub fn string<S: Into<String>>(self, contents: S) -> Self {
+        self.source(EnvSource::string(contents))
+    }
+
+    /// Adds the layered *.env* files for `profile` found in `dir`.
+    ///
+    /// This follows the convention of dotenv-flow. The files are added as
+    /// optional sources in the following order, so that each one overrides
+    /// those before it:
+    ///
+    /// 1. `.env`: defaults shared by every profile
+    /// 2. `.env.local`: local overrides for every profile
+    /// 3. `.env.{profile}`: defaults for the profile
+    /// 4. `.env.{profile}.local`: local overrides for the profile
+    ///
+    /// The profile specific files are skipped if `profile` is `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use dotenvy::EnvLoader;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// EnvLoader::new().layered(".", Some("production")).load()?;
+    /// #     Ok(())
+    /// # }
+    /// ```
+    pub fn layered<P: AsRef<Path>>(self, dir: P, profile: Option<&str>) -> Self {
+        let dir = dir.as_ref();
+        let mut loader = self
+            .source(EnvSource::path(dir.join(".env")).optional())
+            .source(EnvSource::path(dir.join(".env.local")).optional());
+
+        if let Some(profile) = profile {
+            loader = loader
+                .source(EnvSource::path(dir.join(format!(".env.{}", profile))).optional())
+                .source(EnvSource::path(dir.join(format!(".env.{}.local", profile))).optional());
+        }
+
+        loader
+    }
+
+    /// Adds the layered *.env* files found in `dir`, reading the profile from the
+    /// environment variable `key`.
+    ///
+    /// The variable is read from the process environment when this method is called.
+    /// If it is unset or empty, only `.env` and `.env.local` are added.
+    /// See [`layered`](EnvLoader::layered) for the order of the files.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use dotenvy::EnvLoader;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// // loads `.env.staging` and `.env.staging.local` when `APP_ENV=staging`
+    /// EnvLoader::new().layered_from_var(".", "APP_ENV").load()?;
+    /// #     Ok(())
+    /// # }
+    /// ```
+    pub fn layered_from_var<P: AsRef<Path>>(self, dir: P, key: &str) -> Self {
+        let profile = env::var(key).ok().filter(|profile| !profile.is_empty());
+        self.layered(dir, profile.as_deref())
+    }
+
+    /// Sets whether the process environment or the loaded sources win.
+    ///
+    /// Defaults to [`Precedence::Env`].
+    pub fn precedence(mut self, precedence: Precedence) -> Self {
+        self.precedence = precedence;
+        self
+    }
+
+    /// Sets whether substitutions prefer the existing environment or the values
+    /// defined by the sources.
+    ///
+    /// Defaults to the [`precedence`](EnvLoader::precedence) used for loading,
+    /// so that values refer to the same variables that end up in the environment.
+    pub fn substitution_precedence(mut self, precedence: Precedence) -> Self {
+        self.substitution_precedence = Some(precedence);
+        self
+    }
+
+    /// Sets how references to undefined variables are substituted.
+    ///
+    /// Defaults to [`UndefinedVars::Empty`]. With [`UndefinedVars::Collect`], the
+    /// references are listed by [`LoadReport::unresolved`].
+    pub fn undefined_vars(mut self, undefined: UndefinedVars) -> Self {
+        self.undefined = undefined;
+        self
+    }
+
+    /// Allows values to refer to variables defined further down the same source.
+    ///
+    /// See [`Iter::forward_references`] for how the values are resolved.
+    pub fn forward_references(mut self) -> Self {
+        self.forward_references = true;
+        self
+    }
+
+    /// Sets where substitutions look up variables that are not defined by the
+    /// sources, instead of the process environment.
+    ///
+    /// This only affects substitution. Loading still preserves or overrides the
+    /// process environment according to [`precedence`](EnvLoader::precedence).
+    pub fn resolver<V: Resolver + 'a>(mut self, resolver: V) -> Self {
+        self.resolver = Box::new(resolver);
+        self
+    }
+
+    /// Sets which keys are accepted in every source.
+    ///
+    /// Defaults to [`KeyPolicy::Default`].
+    pub fn key_policy(mut self, key_policy: KeyPolicy) -> Self {
+        self.key_policy = key_policy;
+        self
+    }
+
+    /// Sets what happens when a variable is defined more than once.
+    ///
+    /// Defaults to [`DuplicateKeys::LastWins`]. Within a source, substitutions
+    /// refer to the closest definition above regardless.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dotenvy::{DuplicateKeys, EnvLoader, Error};
+    ///
+    /// let result = EnvLoader::new()
+    ///     .string("PORT=8080\nHOST=localhost\nPORT=9090")
+    ///     .duplicate_keys(DuplicateKeys::Error)
+    ///     .into_map();
+    ///
+    /// match result {
+    ///     Err(Error::DuplicateKey(duplicate)) => {
+    ///         assert_eq!(duplicate.key(), "PORT");
+    ///         assert_eq!((duplicate.previous_line(), duplicate.line()), (1, 3));
+    ///     }
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn duplicate_keys(mut self, duplicate_keys: DuplicateKeys) -> Self {
+        self.duplicate_keys = duplicate_keys;
+        self
+    }
+
+    /// Disables substitution, so that `$` is taken literally.
+    pub fn no_substitution(mut self) -> Self {
+        self.substitution = false;
+        self
+    }
+
+    /// Skips the lines that fail to parse or substitute instead of failing,
+    /// so that the valid ones are loaded anyway.
+    ///
+    /// The errors are listed by [`LoadReport::errors`]. Sources that cannot be
+    /// read still fail. See [`Iter::lenient`] for how lines are recovered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dotenvy::EnvLoader;
+    ///
+    /// let report = EnvLoader::new()
+    ///     .string("HOST=localhost\nPORT=\"8080\nDEBUG=true")
+    ///     .lenient()
+    ///     .load_with_report()?;
+    ///
+    /// for (origin, err) in report.errors() {
+    ///     eprintln!("skipped a line of {}: {}", origin, err);
+    /// }
+    /// assert_eq!(report.errors().count(), 1);
+    /// assert_eq!(std::env::var("DEBUG").as_deref(), Ok("true"));
+    /// # Ok::<(), dotenvy::Error>(())
+    /// ```
+    pub fn lenient(mut self) -> Self {
+        self.lenient = true;
+        self
+    }
+
+    /// Reads every source and loads the result into the environment.
+    ///
+    /// Nothing is loaded if any source fails to be read or parsed, unless
+    /// [`lenient`](EnvLoader::lenient).
+    pub fn load(self) -> Result<()> {
+        self.load_with_report().map(|_| ())
+    }
+
+    /// Reads every source and loads the result into the environment,
+    /// returning a report of where each variable came from.
+    ///
+    /// Nothing is loaded if any source fails to be read or parsed, unless
+    /// [`lenient`](EnvLoader::lenient).
+    pub fn load_with_report(self) -> Result<LoadReport> {
+        let precedence = self.precedence;
+        let mut report = self.read_definitions()?;
+
+        let mut seen = HashSet::new();
+        for definition in report.definitions.iter_mut().rev() {
+            if definition.status == Status::Superseded || !seen.insert(definition.key.clone()) {
+                definition.status = Status::Superseded;
+                continue;
+            }
+            let exists = env::var_os(&definition.key).is_some();
+            definition.status = match (exists, precedence) {
+                (false, _) => Status::Set,
+                (true, Precedence::Env) => Status::Skipped,
+                (true, Precedence::File) => Status::Overridden,
+            };
+        }
+
+        for definition in &report.definitions {
+            if let Status::Set | Status::Overridden = definition.status {
+                env::set_var(&definition.key, &definition.value);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Reads every source into an [`EnvMap`] without modifying the environment.
+    ///
+    /// The map holds every variable defined by the sources, regardless of
+    /// [`precedence`](EnvLoader::precedence), which only applies when the map
+    /// is loaded.
+    pub fn into_map(self) -> Result<EnvMap> {
+        Ok(self
+            .read_definitions()?
+            .definitions
+            .into_iter()
+            .filter(|definition| definition.status != Status::Superseded)
+            .map(|definition| (definition.key, definition.value))
+            .collect())
+    }
+
+    /// Reads every definition from every source, in order.
+    ///
+    /// Every definition is reported as [`Status::Set`], except for those losing
+    /// to an earlier one with [`DuplicateKeys::FirstWins`], which are
+    /// [`Status::Superseded`].
+    fn read_definitions(self) -> Result<LoadReport> {
+        let mut report = LoadReport::default();
+        let mut map = EnvMap::new();
+        // where each variable was last defined, or first with `FirstWins`
+        let mut defined: HashMap<String, (Origin, usize)> = HashMap::new();
+        let substitution_precedence = self.substitution_precedence.unwrap_or(self.precedence);
+
+        for source in self.sources {
+            let (origin, iter) = match source.open()? {
+                Some(opened) => opened,
+                None => continue,
+            };
+            let mut iter = iter.resolver(&*self.resolver);
+            iter.remove_bom()?;
+            let seed = map
+                .iter()
+                .map(|(key, value)| (key.to_owned(), Some(value.to_owned())))
+                .collect();
+            iter = iter
+                .with_substitution_data(seed)
+                .undefined_vars(self.undefined)
+                .key_policy(self.key_policy)
+                .substitution_precedence(substitution_precedence);
+            if self.forward_references {
+                iter = iter.forward_references();
+            }
+            if !self.substitution {
+                iter = iter.no_substitution();
+            }
+            if self.lenient {
+                iter = iter.lenient();
+            }
+
+            while let Some(item) = iter.next_entry() {
+                let (line, entry) = match item {
+                    Ok(item) => item,
+                    Err(Error::Io(err)) => return Err(Error::Io(err)),
+                    Err(err) if self.lenient => {
+                        report.errors.push((origin.clone(), err));
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+
+                let mut status = Status::Set;
+                if let Some((previous_origin, previous_line)) = defined.get(&entry.key) {
+                    let duplicate = Duplicate {
+                        key: entry.key.clone(),
+                        previous_origin: previous_origin.clone(),
+                        previous_line: *previous_line,
+                        origin: origin.clone(),
+                        line,
+                    };
+                    match self.duplicate_keys {
+                        DuplicateKeys::LastWins => {}
+                        DuplicateKeys::FirstWins => status = Status::Superseded,
+                        DuplicateKeys::Warn => report.duplicates.push(duplicate),
+                        DuplicateKeys::Error if self.lenient => {
+                            report
+                                .errors
+                                .push((origin.clone(), Error::DuplicateKey(duplicate)));
+                            continue;
+                        }
+                        DuplicateKeys::Error => return Err(Error::DuplicateKey(duplicate)),
+                    }
+                }
+
+                if status == Status::Set {
+                    map.insert(entry.key.clone(), entry.value.clone());
+                    defined.insert(entry.key.clone(), (origin.clone(), line));
+                }
+                report.definitions.push(Definition {
+                    key: entry.key,
+                    value: entry.value,
+                    raw: entry.raw,
+                    origin: origin.clone(),
+                    line,
+                    status,
+                });
+            }
+            report.unresolved.extend(
+                iter.take_unresolved()
+                    .into_iter()
+                    .map(|unresolved| (origin.clone(), unresolved)),
+            );
+        }
+
+        Ok(report)
+    }
+}
+
+impl Default for EnvLoader<'_> {
+    fn default() -> Self {
+        EnvLoader::new()
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::env;
+use std::iter::{self, FromIterator};
+use std::slice;
+use std::vec;
+
+/// An ordered collection of environment variables.
+///
+/// Keys keep the position of their first insertion, so iterating an `EnvMap`
+/// parsed from a *.env* file follows the order of the file. Unlike the
+/// `load*` functions, building an `EnvMap` never touches the process
+/// environment; call [`load`](EnvMap::load) or
+/// [`load_override`](EnvMap::load_override) to apply it explicitly.
+///
+/// # Examples
+///
+/// ```
+/// use dotenvy::EnvMap;
+/// use std::process::Command;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let map = dotenvy::from_read_map("HOST=localhost\nPORT=8080".as_bytes())?;
+/// assert_eq!(map.get("PORT"), Some("8080"));
+///
+/// let mut command = Command::new("server");
+/// command.envs(map.iter());
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvMap {
+    entries: Vec<(String, String)>,
+    positions: HashMap<String, usize>,
+}
+
+impl EnvMap {
+    /// Creates an empty map.
+    pub fn new() -> Self {
+        EnvMap::default()
+    }
+
+    /// Returns the number of variables in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the map contains no variables.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the value of `key`, if present.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.positions
+            .get(key)
+            .map(|&index| self.entries[index].1.as_str())
+    }
+
+    /// Returns `true` if the map contains `key`.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    /// Sets `key` to `value`, returning the previous value if there was one.
+    ///
+    /// Replacing a value keeps the key at its original position.
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) -> Option<String> {
+        let key = key.into();
+        let value = value.into();
+        match self.positions.get(&key) {
+            Some(&index) => Some(std::mem::replace(&mut self.entries[index].1, value)),
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    /// Removes `key`, returning its value if it was present.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(index);
+        for position in self.positions.values_mut() {
+            if *position > index {
+                *position -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    /// Returns an iterator over the `(key, value)` pairs in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Returns an iterator over the keys in order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(key, _)| key.as_str())
+    }
+
+    /// Sets every variable in the process environment,
+    /// preserving any existing environment variables of the same name.
+    pub fn load(&self) {
+        for (key, value) in self.iter() {
+            if env::var_os(key).is_none() {
+                env::set_var(key, value);
+            }
+        }
+    }
+
+    /// Sets every variable in the process environment,
+    /// overriding any existing environment variables of the same name.
+    pub fn load_override(&self) {
+        for (key, value) in self.iter() {
+            env::set_var(key, value);
+        }
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> Extend<(K, V)> for EnvMap {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for EnvMap {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = EnvMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl IntoIterator for EnvMap {
+    type Item = (String, String);
+    type IntoIter = vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a EnvMap {
+    type Item = (&'a str, &'a str);
+    type IntoIter = iter::Map<
+        slice::Iter<'a, (String, String)>,
+        fn(&'a (String, String)) -> (&'a str, &'a str),
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn map() -> EnvMap {
+        vec![("A", "1"), ("B", "2"), ("C", "3")]
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_insert_keeps_position() {
+        let mut map = map();
+        assert_eq!(map.insert("A", "4"), Some("1".to_string()));
+        assert_eq!(map.insert("D", "5"), None);
+
+        let entries: Vec<_> = map.iter().collect();
+        assert_eq!(
+            entries,
+            vec![("A", "4"), ("B", "2"), ("C", "3"), ("D", "5")]
+        );
+    }
+
+    #[test]
+    fn test_remove_reindexes() {
+        let mut map = map();
+        assert_eq!(map.remove("A"), Some("1".to_string()));
+        assert_eq!(map.remove("A"), None);
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("B"), Some("2"));
+        assert_eq!(map.get("C"), Some("3"));
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec!["B", "C"]);
+    }
+
+    #[test]
+    fn test_later_duplicates_win() {
+        let map: EnvMap = vec![("A", "1"), ("A", "2")].into_iter().collect();
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get("A"), Some("2"));
+    }
+}
@@ -0,0 +1,391 @@
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, Read};
+
+use crate::errors::*;
+use crate::iter::Iter;
+use crate::loader::Precedence;
+use crate::parse::{KeyPolicy, UndefinedVars};
+use crate::resolver::{ProcessEnv, Resolver};
+
+/// The first of the private use characters standing for the bytes `0x80..=0xFF`
+/// that are not part of valid UTF-8.
+const RAW_BYTE_BASE: u32 = 0x10_FF00;
+
+/// An iterator over the variables of a reader, yielding keys and values as
+/// [`OsString`]s.
+///
+/// Unlike [`Iter`], bytes that are not valid UTF-8 are kept instead of failing
+/// with an I/O error. On Unix, they are carried through to the values unchanged,
+/// so that for instance Latin-1 paths can be loaded. On other platforms they are
+/// replaced by U+FFFD, as values must be valid Unicode there.
+///
+/// Keys must still be valid UTF-8, and substitutions look up variables whose
+/// value is not valid Unicode as undefined.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(unix)]
+/// # fn main() -> Result<(), dotenvy::Error> {
+/// use std::os::unix::ffi::OsStrExt;
+///
+/// let mut iter = dotenvy::OsIter::new(&b"PATH_LATIN1=/tmp/caf\xe9\n"[..]);
+/// let (key, value) = iter.next().unwrap()?;
+/// assert_eq!(key, "PATH_LATIN1");
+/// assert_eq!(value.as_bytes(), b"/tmp/caf\xe9");
+/// #     Ok(())
+/// # }
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+pub struct OsIter<R, V = ProcessEnv> {
+    iter: Iter<RawBytes<R>, Encoded<V>>,
+}
+
+impl<R: Read> OsIter<R> {
+    pub fn new(reader: R) -> OsIter<R> {
+        OsIter {
+            iter: Iter::new(RawBytes::new(reader))
+                .raw_bytes()
+                .resolver(Encoded(ProcessEnv)),
+        }
+    }
+}
+
+impl<R: Read, V: Resolver> OsIter<R, V> {
+    /// See [`Iter::source_name`].
+    pub fn source_name(self, name: impl Into<String>) -> Self {
+        OsIter {
+            iter: self.iter.source_name(name),
+        }
+    }
+
+    /// See [`Iter::resolver`].
+    pub fn resolver<W: Resolver>(self, resolver: W) -> OsIter<R, W> {
+        OsIter {
+            iter: self.iter.resolver(Encoded(resolver)),
+        }
+    }
+
+    /// See [`Iter::key_policy`].
+    pub fn key_policy(self, key_policy: KeyPolicy) -> Self {
+        OsIter {
+            iter: self.iter.key_policy(key_policy),
+        }
+    }
+
+    /// See [`Iter::no_substitution`].
+    pub fn no_substitution(self) -> Self {
+        OsIter {
+            iter: self.iter.no_substitution(),
+        }
+    }
+
+    /// See [`Iter::forward_references`].
+    pub fn forward_references(self) -> Self {
+        OsIter {
+            iter: self.iter.forward_references(),
+        }
+    }
+
+    /// See [`Iter::lenient`].
+    pub fn lenient(self) -> Self {
+        OsIter {
+            iter: self.iter.lenient(),
+        }
+    }
+
+    /// See [`Iter::undefined_vars`].
+    pub fn undefined_vars(self, undefined: UndefinedVars) -> Self {
+        OsIter {
+            iter: self.iter.undefined_vars(undefined),
+        }
+    }
+
+    /// See [`Iter::substitution_precedence`].
+    pub fn substitution_precedence(self, precedence: Precedence) -> Self {
+        OsIter {
+            iter: self.iter.substitution_precedence(precedence),
+        }
+    }
+
+    /// Loads all variables found in the `reader` into the environment,
+    /// preserving any existing environment variables of the same name.
+    ///
+    /// If a variable is specified multiple times within the reader's data,
+    /// then the first occurrence is applied.
+    pub fn load(mut self) -> Result<()> {
+        self.iter.remove_bom()?;
+        self.iter.default_precedence(Precedence::Env);
+
+        for item in self {
+            let (key, value) = item?;
+            if env::var_os(&key).is_none() {
+                env::set_var(&key, value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads all variables found in the `reader` into the environment,
+    /// overriding any existing environment variables of the same name.
+    ///
+    /// If a variable is specified multiple times within the reader's data,
+    /// then the last occurrence is applied.
+    pub fn load_override(mut self) -> Result<()> {
+        self.iter.remove_bom()?;
+        self.iter.default_precedence(Precedence::File);
+
+        for item in self {
+            let (key, value) = item?;
+            env::set_var(key, value);
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read, V: Resolver> Iterator for OsIter<R, V> {
+    type Item = Result<(OsString, OsString)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter
+            .next()
+            .map(|result| result.map(|(key, value)| (decode(&key), decode(&value))))
+    }
+}
+
+/// A reader turning any input into valid UTF-8, so that it can be parsed.
+///
+/// Each byte that is not part of valid UTF-8 is replaced by the private use
+/// character `RAW_BYTE_BASE + byte`. The bytes of those characters are replaced
+/// as well when they appear in the input, so that [`decode`] restores the input
+/// exactly.
+struct RawBytes<R> {
+    inner: R,
+    /// Input bytes not converted yet, such as an incomplete UTF-8 sequence.
+    input: Vec<u8>,
+    output: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> RawBytes<R> {
+    fn new(inner: R) -> Self {
+        RawBytes {
+            inner,
+            input: Vec::new(),
+            output: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Converts the next chunk of input, returning `false` at the end of it.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; 8 * 1024];
+        loop {
+            let n = match self.inner.read(&mut chunk) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.input.extend_from_slice(&chunk[..n]);
+
+            let mut output = String::new();
+            let consumed = encode(&self.input, n == 0, &mut output);
+            self.input.drain(..consumed);
+            self.output = output.into_bytes();
+            self.pos = 0;
+
+            if !self.output.is_empty() {
+                return Ok(true);
+            }
+            if n == 0 {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for RawBytes<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.output.len() && !self.fill()? {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.output.len() - self.pos);
+        buf[..n].copy_from_slice(&self.output[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// A resolver encoding the values of another one like [`RawBytes`] encodes
+/// its input.
+struct Encoded<V>(V);
+
+impl<V: Resolver> Resolver for Encoded<V> {
+    fn resolve(&self, name: &str) -> Option<String> {
+        self.0.resolve(name).map(|value| {
+            let mut encoded = String::with_capacity(value.len());
+            value.chars().for_each(|c| push_encoded(&mut encoded, c));
+            encoded
+        })
+    }
+}
+
+/// Converts `input` into `output`, returning the number of bytes converted.
+///
+/// An incomplete UTF-8 sequence at the end is left for the next call, unless
+/// this is the end of the input.
+fn encode(input: &[u8], end: bool, output: &mut String) -> usize {
+    let mut rest = input;
+    loop {
+        let (valid, invalid) = match std::str::from_utf8(rest) {
+            Ok(valid) => (valid, None),
+            Err(err) => {
+                let (valid, _) = rest.split_at(err.valid_up_to());
+                // the prefix is valid UTF-8 by definition
+                let valid = std::str::from_utf8(valid).unwrap_or_default();
+                (valid, Some(err.error_len()))
+            }
+        };
+        valid.chars().for_each(|c| push_encoded(output, c));
+        rest = &rest[valid.len()..];
+
+        match invalid {
+            None => return input.len(),
+            Some(Some(len)) => {
+                rest[..len].iter().for_each(|&b| push_raw_byte(output, b));
+                rest = &rest[len..];
+            }
+            Some(None) if end => {
+                rest.iter().for_each(|&b| push_raw_byte(output, b));
+                return input.len();
+            }
+            Some(None) => return input.len() - rest.len(),
+        }
+    }
+}
+
+/// Appends `c` to `output`, replacing it by its bytes if it stands for a raw
+/// byte, so that [`decode`] restores it rather than the byte.
+pub(crate) fn push_encoded(output: &mut String, c: char) {
+    if is_raw_byte(c) {
+        let mut bytes = [0; 4];
+        c.encode_utf8(&mut bytes)
+            .bytes()
+            .for_each(|b| push_raw_byte(output, b));
+    } else {
+        output.push(c);
+    }
+}
+
+fn push_raw_byte(output: &mut String, byte: u8) {
+    // bytes below 0x80 are always valid UTF-8
+    output.extend(std::char::from_u32(RAW_BYTE_BASE + u32::from(byte)));
+}
+
+fn is_raw_byte(c: char) -> bool {
+    (RAW_BYTE_BASE + 0x80..=RAW_BYTE_BASE + 0xFF).contains(&u32::from(c))
+}
+
+/// Restores the bytes replaced by [`RawBytes`].
+fn decode(s: &str) -> OsString {
+    let mut bytes = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        if is_raw_byte(c) {
+            bytes.push((u32::from(c) - RAW_BYTE_BASE) as u8);
+        } else {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+    }
+    from_bytes(bytes)
+}
+
+#[cfg(unix)]
+fn from_bytes(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn from_bytes(bytes: Vec<u8>) -> OsString {
+    String::from_utf8_lossy(&bytes).into_owned().into()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn roundtrip(input: &[u8], chunk: usize) -> Vec<u8> {
+        let mut output = String::new();
+        let mut pending = Vec::new();
+        for (i, piece) in input.chunks(chunk).enumerate() {
+            pending.extend_from_slice(piece);
+            let end = (i + 1) * chunk >= input.len();
+            let consumed = encode(&pending, end, &mut output);
+            pending.drain(..consumed);
+        }
+        assert!(pending.is_empty());
+        output
+            .chars()
+            .flat_map(|c| {
+                if is_raw_byte(c) {
+                    vec![(u32::from(c) - RAW_BYTE_BASE) as u8]
+                } else {
+                    c.to_string().into_bytes()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let input = "caf\u{e9} \u{10FFAB} \u{1F600}"
+            .bytes()
+            .chain(vec![0xE9, b'x', 0xF0, 0x9F, 0xFF, 0xC3])
+            .collect::<Vec<_>>();
+        for chunk in 1..=input.len() {
+            assert_eq!(roundtrip(&input, chunk), input, "chunk size {}", chunk);
+        }
+    }
+
+    #[test]
+    fn test_os_iter_keeps_invalid_utf8() {
+        let mut iter = OsIter::new(&b"A=caf\xe9\nB=\"${A}/\xff\"\nC='ok'\n"[..]);
+
+        let (key, value) = iter.next().unwrap().unwrap();
+        assert_eq!(key, "A");
+        assert_eq!(value, from_bytes(b"caf\xe9".to_vec()));
+
+        let (key, value) = iter.next().unwrap().unwrap();
+        assert_eq!(key, "B");
+        assert_eq!(value, from_bytes(b"caf\xe9/\xff".to_vec()));
+
+        let (key, value) = iter.next().unwrap().unwrap();
+        assert_eq!(key, "C");
+        assert_eq!(value, "ok");
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_os_iter_keeps_raw_byte_characters() {
+        let resolver = vec![("R".to_string(), "\u{10FFE9}".to_string())]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let mut iter = OsIter::new(&b"E=\"\\u{10FF80}\"\nR=${R}\n"[..]).resolver(resolver);
+
+        let (key, value) = iter.next().unwrap().unwrap();
+        assert_eq!(key, "E");
+        assert_eq!(value, "\u{10FF80}");
+
+        let (key, value) = iter.next().unwrap().unwrap();
+        assert_eq!(key, "R");
+        assert_eq!(value, "\u{10FFE9}");
+        assert!(iter.next().is_none());
+    }
+}
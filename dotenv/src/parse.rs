@@ -1,22 +1,362 @@
 use std::collections::HashMap;
-use std::env;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
 
 use crate::errors::*;
+use crate::loader::Precedence;
+use crate::os;
+use crate::report::Unresolved;
+use crate::resolver::Resolver;
+
+/// A variable definition parsed from a single logical line.
+pub struct Entry {
+    pub key: String,
+    pub value: String,
+    /// The value as written, before unquoting and substitution.
+    pub raw: String,
+}
+
+/// A variable definition whose value has not been substituted yet.
+pub struct Unexpanded {
+    pub key: String,
+    template: Template,
+    /// The value as written, before unquoting and substitution.
+    pub raw: String,
+}
+
+impl Unexpanded {
+    /// Returns the names of the variables the value refers to, including those
+    /// only used by expansion operators.
+    pub fn references(&self) -> Vec<&str> {
+        fn collect<'a>(template: &'a [Segment], names: &mut Vec<&'a str>) {
+            for segment in template {
+                if let Segment::Var(expansion) = segment {
+                    names.push(&expansion.name);
+                    if let Some(modifier) = &expansion.modifier {
+                        collect(&modifier.word, names);
+                    }
+                }
+            }
+        }
+
+        let mut names = Vec::new();
+        collect(&self.template, &mut names);
+        names
+    }
+
+    /// Returns the names of the variables the value refers to without an
+    /// expansion operator, which are replaced by the empty string if undefined.
+    pub fn plain_references(&self) -> Vec<&str> {
+        fn collect<'a>(template: &'a [Segment], names: &mut Vec<&'a str>) {
+            for segment in template {
+                if let Segment::Var(expansion) = segment {
+                    match &expansion.modifier {
+                        Some(modifier) => collect(&modifier.word, names),
+                        None => names.push(&expansion.name),
+                    }
+                }
+            }
+        }
+
+        let mut names = Vec::new();
+        collect(&self.template, &mut names);
+        names
+    }
+
+    /// Returns the literal text of the value, leaving out any variable
+    /// references, as when parsed without substitution.
+    pub fn literal(&self) -> String {
+        self.template
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Literal(text) => Some(text.as_str()),
+                Segment::Var(_) => None,
+            })
+            .collect()
+    }
+}
 
 // for readability's sake
-pub type ParsedLine = Result<Option<(String, String)>>;
+pub type ParsedLine = Result<Option<Entry>>;
+
+/// How references to variables that are not defined are substituted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UndefinedVars {
+    /// Substitute the empty string.
+    #[default]
+    Empty,
+    /// Fail with [`Error::Substitution`].
+    Error,
+    /// Substitute the empty string and record the reference, so it can be
+    /// reported with [`Iter::unresolved`](crate::Iter::unresolved).
+    Collect,
+}
+
+/// Which keys are accepted.
+#[derive(Clone, Copy, Default)]
+pub enum KeyPolicy {
+    /// ASCII letters, digits, `_` and `.`, not starting with a digit or `.`.
+    #[default]
+    Default,
+    /// POSIX names: ASCII letters, digits and `_`, not starting with a digit.
+    Posix,
+    /// Unicode letters and digits, `_`, `-`, `.` and `:`, as some container
+    /// tools accept.
+    ///
+    /// Substitutions cannot refer to keys containing `-` or `:`.
+    Permissive,
+    /// Keys for which the function returns `true`.
+    ///
+    /// A key is followed by `=` or whitespace, so it never contains either.
+    Custom(fn(&str) -> bool),
+}
+
+impl KeyPolicy {
+    /// Returns whether `key` is accepted.
+    pub fn is_valid(&self, key: &str) -> bool {
+        let mut chars = key.chars();
+        let first = match chars.next() {
+            Some(first) => first,
+            None => return false,
+        };
+        match self {
+            KeyPolicy::Default => {
+                (first.is_ascii_alphabetic() || first == '_')
+                    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+            }
+            KeyPolicy::Posix => {
+                (first.is_ascii_alphabetic() || first == '_')
+                    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }
+            KeyPolicy::Permissive => key
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':')),
+            KeyPolicy::Custom(accept) => accept(key),
+        }
+    }
+
+    /// Describes the accepted keys, for error messages.
+    pub(crate) fn describe(&self) -> &'static str {
+        match self {
+            KeyPolicy::Default => {
+                "keys must start with a letter or `_` and contain only ASCII letters, digits, `_` and `.`"
+            }
+            KeyPolicy::Posix => {
+                "POSIX keys must start with a letter or `_` and contain only ASCII letters, digits and `_`"
+            }
+            KeyPolicy::Permissive => {
+                "keys must contain only letters, digits, `_`, `-`, `.` and `:`"
+            }
+            KeyPolicy::Custom(_) => "the key is rejected by the custom key policy",
+        }
+    }
+}
+
+impl fmt::Debug for KeyPolicy {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyPolicy::Default => write!(fmt, "Default"),
+            KeyPolicy::Posix => write!(fmt, "Posix"),
+            KeyPolicy::Permissive => write!(fmt, "Permissive"),
+            KeyPolicy::Custom(_) => write!(fmt, "Custom(..)"),
+        }
+    }
+}
 
+/// The substitution state carried from one line to the next.
+#[derive(Default)]
+pub struct Substitution {
+    /// The variables defined so far, `None` for those defined as empty.
+    pub data: HashMap<String, Option<String>>,
+    pub undefined: UndefinedVars,
+    /// Whether the resolver or earlier lines win, if set explicitly.
+    pub precedence: Option<Precedence>,
+    /// Whether `$` is taken literally.
+    pub disabled: bool,
+    /// Whether escapes are encoded for [`OsIter`](crate::OsIter).
+    pub raw_bytes: bool,
+    /// The references recorded by [`UndefinedVars::Collect`].
+    pub unresolved: Vec<Unresolved>,
+}
+
+/// Parses a logical line, `line_number` being the line it starts on.
 pub fn parse_line(
     line: &str,
-    substitution_data: &mut HashMap<String, Option<String>>,
+    line_number: usize,
+    substitution: &mut Substitution,
+    resolver: &dyn Resolver,
+    key_policy: KeyPolicy,
 ) -> ParsedLine {
-    let mut parser = LineParser::new(line, substitution_data);
+    match parse_unexpanded(
+        line,
+        !substitution.disabled,
+        substitution.raw_bytes,
+        key_policy,
+    )? {
+        Some(unexpanded) => substitution
+            .expand_entry(unexpanded, line_number, resolver)
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Parses a logical line without substituting variables.
+///
+/// Unless `substitute` is set, `$` is taken literally. If `raw_bytes` is set,
+/// characters that escapes yield are encoded as [`OsIter`](crate::OsIter) reads
+/// them.
+pub fn parse_unexpanded(
+    line: &str,
+    substitute: bool,
+    raw_bytes: bool,
+    key_policy: KeyPolicy,
+) -> Result<Option<Unexpanded>> {
+    let mut parser = LineParser::new(line, substitute, raw_bytes, key_policy);
     parser.parse_line()
 }
 
+impl Substitution {
+    /// Substitutes the variables in the value of `entry`, defined on `line_number`,
+    /// and makes it available to the following definitions.
+    pub fn expand_entry(
+        &mut self,
+        entry: Unexpanded,
+        line_number: usize,
+        resolver: &dyn Resolver,
+    ) -> Result<Entry> {
+        let value = self.expand(&entry.key, line_number, &entry.template, resolver)?;
+        let defined = if entry.template.is_empty() {
+            None
+        } else {
+            Some(value.clone())
+        };
+        self.data.insert(entry.key.clone(), defined);
+
+        Ok(Entry {
+            key: entry.key,
+            value,
+            raw: entry.raw,
+        })
+    }
+
+    /// Substitutes the variables referenced by `template`, which is the value
+    /// of `key` defined on `line`.
+    fn expand(
+        &mut self,
+        key: &str,
+        line: usize,
+        template: &[Segment],
+        resolver: &dyn Resolver,
+    ) -> Result<String> {
+        let mut output = String::new();
+        for segment in template {
+            match segment {
+                Segment::Literal(text) => output.push_str(text),
+                Segment::Var(expansion) => {
+                    output.push_str(&self.expand_var(key, line, expansion, resolver)?)
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    fn expand_var(
+        &mut self,
+        key: &str,
+        line: usize,
+        expansion: &Expansion,
+        resolver: &dyn Resolver,
+    ) -> Result<String> {
+        let value = self.lookup(&expansion.name, resolver);
+        let modifier = match &expansion.modifier {
+            Some(modifier) => modifier,
+            None => {
+                return match value {
+                    Some(value) => Ok(value),
+                    None => self.undefined(key, line, &expansion.name),
+                }
+            }
+        };
+
+        let set = match &value {
+            Some(value) => !(modifier.colon && value.is_empty()),
+            None => false,
+        };
+        match (modifier.operator, set) {
+            (Operator::Default, false) => self.expand(key, line, &modifier.word, resolver),
+            (Operator::Assign, false) => {
+                let word = self.expand(key, line, &modifier.word, resolver)?;
+                self.data.insert(expansion.name.clone(), Some(word.clone()));
+                Ok(word)
+            }
+            (Operator::Error, false) => {
+                let message = self.expand(key, line, &modifier.word, resolver)?;
+                Err(Error::Substitution(SubstitutionError::new(
+                    expansion.name.clone(),
+                    key.to_owned(),
+                    line,
+                    Some(message).filter(|message| !message.is_empty()),
+                )))
+            }
+            (Operator::Alternative, true) => self.expand(key, line, &modifier.word, resolver),
+            (Operator::Alternative, false) => Ok(String::new()),
+            (_, true) => Ok(value.unwrap_or_default()),
+        }
+    }
+
+    /// Returns the value of `name` from the resolver or earlier lines,
+    /// whichever takes precedence.
+    fn lookup(&self, name: &str, resolver: &dyn Resolver) -> Option<String> {
+        let defined = || {
+            self.data
+                .get(name)
+                .map(|value| value.clone().unwrap_or_default())
+        };
+        match self.precedence.unwrap_or_default() {
+            Precedence::Env => resolver.resolve(name).or_else(defined),
+            Precedence::File => defined().or_else(|| resolver.resolve(name)),
+        }
+    }
+
+    /// Substitutes a reference to the undefined variable `name`.
+    fn undefined(&mut self, key: &str, line: usize, name: &str) -> Result<String> {
+        match self.undefined {
+            UndefinedVars::Empty => {}
+            UndefinedVars::Error => {
+                return Err(Error::Substitution(SubstitutionError::new(
+                    name.to_owned(),
+                    key.to_owned(),
+                    line,
+                    None,
+                )))
+            }
+            UndefinedVars::Collect => self.unresolved.push(Unresolved {
+                var: name.to_owned(),
+                key: key.to_owned(),
+                line,
+            }),
+        }
+        Ok(String::new())
+    }
+}
+
+/// Returns whether `line` starts like a definition, with a valid key followed by `=`.
+pub fn is_definition(line: &str, key_policy: KeyPolicy) -> bool {
+    match line.split_once('=') {
+        Some((key, _)) => {
+            let key = key.trim();
+            let key = key.strip_prefix("export ").unwrap_or(key).trim_start();
+            key_policy.is_valid(key)
+        }
+        None => false,
+    }
+}
+
 struct LineParser<'a> {
-    original_line: &'a str,
-    substitution_data: &'a mut HashMap<String, Option<String>>,
+    substitute: bool,
+    raw_bytes: bool,
+    key_policy: KeyPolicy,
     line: &'a str,
     pos: usize,
 }
@@ -24,21 +364,24 @@ struct LineParser<'a> {
 impl<'a> LineParser<'a> {
     fn new(
         line: &'a str,
-        substitution_data: &'a mut HashMap<String, Option<String>>,
+        substitute: bool,
+        raw_bytes: bool,
+        key_policy: KeyPolicy,
     ) -> LineParser<'a> {
         LineParser {
-            original_line: line,
-            substitution_data,
+            substitute,
+            raw_bytes,
+            key_policy,
             line: line.trim_end(), // we don’t want trailing whitespace
             pos: 0,
         }
     }
 
-    fn err(&self) -> Error {
-        Error::LineParse(self.original_line.into(), self.pos)
+    fn err(&self, kind: ParseErrorKind) -> Error {
+        Error::Parse(ParseError::new(kind, self.pos))
     }
 
-    fn parse_line(&mut self) -> ParsedLine {
+    fn parse_line(&mut self) -> Result<Option<Unexpanded>> {
         self.skip_whitespace();
         // if its an empty line or a comment, skip it
         if self.line.is_empty() || self.line.starts_with('#') {
@@ -62,31 +405,43 @@ impl<'a> LineParser<'a> {
         self.skip_whitespace();
 
         if self.line.is_empty() || self.line.starts_with('#') {
-            self.substitution_data.insert(key.clone(), None);
-            return Ok(Some((key, String::new())));
+            return Ok(Some(Unexpanded {
+                key,
+                template: Template::new(),
+                raw: String::new(),
+            }));
         }
 
-        let parsed_value = parse_value(self.line, self.substitution_data)?;
-        self.substitution_data
-            .insert(key.clone(), Some(parsed_value.clone()));
-
-        Ok(Some((key, parsed_value)))
+        let (template, raw_len) = parse_value(self.line, self.substitute, self.raw_bytes)
+            .map_err(|err| Error::Parse(err.shift(self.pos)))?;
+        Ok(Some(Unexpanded {
+            key,
+            template,
+            raw: self.line[..raw_len].trim_end().to_owned(),
+        }))
     }
 
     fn parse_key(&mut self) -> Result<String> {
-        if !self
-            .line
-            .starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
-        {
-            return Err(self.err());
-        }
-        let index = match self
-            .line
-            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
-        {
+        let index = match self.line.find(|c: char| c == '=' || c.is_whitespace()) {
             Some(index) => index,
             None => self.line.len(),
         };
+        let candidate = &self.line[..index];
+        if !self.key_policy.is_valid(candidate) {
+            // only report the key as invalid when the line is otherwise a definition
+            let kind = if !self.line[index..].trim_start().starts_with('=') {
+                self.pos += index;
+                ParseErrorKind::MissingEquals
+            } else if candidate.is_empty() {
+                ParseErrorKind::MissingKey
+            } else {
+                ParseErrorKind::InvalidKey(InvalidKeyError::new(
+                    candidate.to_owned(),
+                    self.key_policy,
+                ))
+            };
+            return Err(self.err(kind));
+        }
         self.pos += index;
         let key = String::from(&self.line[..index]);
         self.line = &self.line[index..];
@@ -95,7 +450,7 @@ impl<'a> LineParser<'a> {
 
     fn expect_equal(&mut self) -> Result<()> {
         if !self.line.starts_with('=') {
-            return Err(self.err());
+            return Err(self.err(ParseErrorKind::MissingEquals));
         }
         self.line = &self.line[1..];
         self.pos += 1;
@@ -113,29 +468,131 @@ impl<'a> LineParser<'a> {
     }
 }
 
-#[derive(Eq, PartialEq)]
-enum SubstitutionMode {
-    None,
-    Block,
-    EscapedBlock,
+/// A value split into literal text and variable references.
+type Template = Vec<Segment>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Var(Expansion),
+}
+
+/// A `$NAME` or `${NAME}` reference, optionally with an expansion operator
+/// such as `${NAME:-default}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Expansion {
+    name: String,
+    modifier: Option<Modifier>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Modifier {
+    operator: Operator,
+    /// With a colon, a variable set to the empty string is treated as unset.
+    colon: bool,
+    word: Template,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    /// `-`: use the word if the variable is unset.
+    Default,
+    /// `=`: use the word and assign it to the variable if it is unset.
+    Assign,
+    /// `?`: fail with the word as message if the variable is unset.
+    Error,
+    /// `+`: use the word if the variable is set, and nothing otherwise.
+    Alternative,
+}
+
+/// Appends `c` to the literal text at the end of `template`.
+fn push_literal(template: &mut Template, c: char) {
+    if let Some(Segment::Literal(text)) = template.last_mut() {
+        text.push(c);
+    } else {
+        template.push(Segment::Literal(c.to_string()));
+    }
+}
+
+/// Appends a reference to `name`, unless it is empty as for a lone `$`.
+fn push_var(template: &mut Template, name: String) {
+    if !name.is_empty() {
+        template.push(Segment::Var(Expansion {
+            name,
+            modifier: None,
+        }));
+    }
 }
 
+/// Parses a value, returning it along with the length of `input` it spans,
+/// excluding any trailing comment.
+///
+/// Unless `substitute` is set, `$` is taken literally. If `raw_bytes` is set,
+/// escapes yielding characters that stand for raw bytes are encoded, so that
+/// they are kept as they are.
 fn parse_value(
     input: &str,
-    substitution_data: &mut HashMap<String, Option<String>>,
-) -> Result<String> {
+    substitute: bool,
+    raw_bytes: bool,
+) -> std::result::Result<(Template, usize), ParseError> {
+    if let Some(heredoc) = heredoc_value(input) {
+        return Ok((heredoc.parse_body(input, substitute), input.len()));
+    }
+
     let mut strong_quote = false; // '
+    let mut backtick_quote = false; // `
     let mut weak_quote = false; // "
     let mut escaped = false;
     let mut expecting_end = false;
+    // where the last quote was opened
+    let mut quote_start = 0;
+
+    let mut output = Template::new();
 
-    //FIXME can this be done without yet another allocation per line?
-    let mut output = String::new();
+    // the name of a `$NAME` reference being read, and where it starts
+    let mut substitution_name: Option<String> = None;
+    let mut substitution_start = 0;
+    // the end of a `${...}` reference being skipped over
+    let mut block_end = None;
+    let mut value_len = input.len();
+
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(byte_index, c)) = chars.peek() {
+        if let Some(name) = substitution_name.as_mut() {
+            if c.is_alphanumeric() {
+                name.push(c);
+                chars.next();
+                continue;
+            }
+            let name = substitution_name.take().unwrap_or_default();
+            if c == '{' && name.is_empty() {
+                let content = &input[byte_index + 1..];
+                match braced_len(content) {
+                    Some(len) => {
+                        output.push(Segment::Var(parse_expansion(&content[..len])));
+                        block_end = Some(byte_index + 1 + len);
+                    }
+                    None => {
+                        block_end = Some(input.len());
+                        break;
+                    }
+                }
+                chars.next();
+                continue;
+            }
+            push_var(&mut output, name);
+            // the character ending the name is parsed as usual
+            continue;
+        }
+        chars.next();
 
-    let mut substitution_mode = SubstitutionMode::None;
-    let mut substitution_name = String::new();
+        if let Some(end) = block_end {
+            if byte_index == end {
+                block_end = None;
+            }
+            continue;
+        }
 
-    for (index, c) in input.chars().enumerate() {
         //the regex _should_ already trim whitespace off the end
         //expecting_end is meant to permit: k=v #comment
         //without affecting: k=v#comment
@@ -144,20 +601,49 @@ fn parse_value(
             if c == ' ' || c == '\t' {
                 continue;
             } else if c == '#' {
+                value_len = byte_index;
                 break;
             } else {
-                return Err(Error::LineParse(input.to_owned(), index));
+                return Err(ParseError::new(
+                    ParseErrorKind::TrailingCharacters,
+                    byte_index,
+                ));
             }
+        } else if escaped && (c == '\n' || c == '\r') {
+            // a backslash at the end of a line continues the value on the next one
+            if let Some(&(_, '\n')) = chars.peek() {
+                chars.next();
+            }
+            if !weak_quote {
+                while let Some(&(_, ' ' | '\t')) = chars.peek() {
+                    chars.next();
+                }
+            }
+
+            escaped = false;
+        } else if escaped && weak_quote {
+            match weak_escape(byte_index, c, &mut chars) {
+                Ok(Some(decoded)) if raw_bytes => {
+                    let mut encoded = String::new();
+                    os::push_encoded(&mut encoded, decoded);
+                    encoded.chars().for_each(|c| push_literal(&mut output, c));
+                }
+                Ok(Some(decoded)) => push_literal(&mut output, decoded),
+                // unknown escapes are kept as they are
+                Ok(None) => {
+                    push_literal(&mut output, '\\');
+                    push_literal(&mut output, c);
+                }
+                Err(index) => return Err(ParseError::new(ParseErrorKind::InvalidEscape, index)),
+            }
+
+            escaped = false;
         } else if escaped {
-            //TODO I tried handling literal \r but various issues
-            //imo not worth worrying about until there's a use case
-            //(actually handling backslash 0x10 would be a whole other matter)
-            //then there's \v \f bell hex... etc
             match c {
-                '\\' | '\'' | '"' | '$' | ' ' => output.push(c),
-                'n' => output.push('\n'), // handle \n case
+                '\\' | '\'' | '"' | '$' | ' ' => push_literal(&mut output, c),
+                'n' => push_literal(&mut output, '\n'), // handle \n case
                 _ => {
-                    return Err(Error::LineParse(input.to_owned(), index));
+                    return Err(ParseError::new(ParseErrorKind::InvalidEscape, byte_index));
                 }
             }
 
@@ -166,111 +652,344 @@ fn parse_value(
             if c == '\'' {
                 strong_quote = false;
             } else {
-                output.push(c);
-            }
-        } else if substitution_mode != SubstitutionMode::None {
-            if c.is_alphanumeric() {
-                substitution_name.push(c);
-            } else {
-                match substitution_mode {
-                    SubstitutionMode::None => unreachable!(),
-                    SubstitutionMode::Block => {
-                        if c == '{' && substitution_name.is_empty() {
-                            substitution_mode = SubstitutionMode::EscapedBlock;
-                        } else {
-                            apply_substitution(
-                                substitution_data,
-                                &std::mem::take(&mut substitution_name),
-                                &mut output,
-                            );
-                            if c == '$' {
-                                substitution_mode = if !strong_quote && !escaped {
-                                    SubstitutionMode::Block
-                                } else {
-                                    SubstitutionMode::None
-                                }
-                            } else {
-                                substitution_mode = SubstitutionMode::None;
-                                output.push(c);
-                            }
-                        }
-                    }
-                    SubstitutionMode::EscapedBlock => {
-                        if c == '}' {
-                            substitution_mode = SubstitutionMode::None;
-                            apply_substitution(
-                                substitution_data,
-                                &std::mem::take(&mut substitution_name),
-                                &mut output,
-                            );
-                        } else {
-                            substitution_name.push(c);
-                        }
-                    }
-                }
+                push_literal(&mut output, c);
             }
-        } else if c == '$' {
-            substitution_mode = if !strong_quote && !escaped {
-                SubstitutionMode::Block
+        } else if backtick_quote {
+            if c == '`' {
+                backtick_quote = false;
             } else {
-                SubstitutionMode::None
+                push_literal(&mut output, c);
             }
+        } else if c == '$' && substitute {
+            substitution_name = Some(String::new());
+            substitution_start = byte_index;
         } else if weak_quote {
             if c == '"' {
                 weak_quote = false;
             } else if c == '\\' {
                 escaped = true;
             } else {
-                output.push(c);
+                push_literal(&mut output, c);
             }
-        } else if c == '\'' {
-            strong_quote = true;
-        } else if c == '"' {
-            weak_quote = true;
+        } else if c == '\'' || c == '"' || c == '`' {
+            strong_quote = c == '\'';
+            weak_quote = c == '"';
+            backtick_quote = c == '`';
+            quote_start = byte_index;
         } else if c == '\\' {
             escaped = true;
         } else if c == ' ' || c == '\t' {
             expecting_end = true;
         } else {
-            output.push(c);
+            push_literal(&mut output, c);
         }
     }
 
-    //XXX also fail if escaped? or...
-    if substitution_mode == SubstitutionMode::EscapedBlock || strong_quote || weak_quote {
-        let value_length = input.len();
-        Err(Error::LineParse(
-            input.to_owned(),
-            if value_length == 0 {
-                0
-            } else {
-                value_length - 1
-            },
+    if block_end.is_some() {
+        Err(ParseError::new(
+            ParseErrorKind::UnterminatedSubstitution,
+            substitution_start,
+        ))
+    } else if strong_quote || weak_quote || backtick_quote {
+        Err(ParseError::new(
+            ParseErrorKind::UnterminatedQuote,
+            quote_start,
         ))
     } else {
-        apply_substitution(
-            substitution_data,
-            &std::mem::take(&mut substitution_name),
-            &mut output,
-        );
-        Ok(output)
+        if let Some(name) = substitution_name {
+            push_var(&mut output, name);
+        }
+        Ok((output, value_len))
     }
 }
 
-fn apply_substitution(
-    substitution_data: &mut HashMap<String, Option<String>>,
-    substitution_name: &str,
-    output: &mut String,
-) {
-    if let Ok(environment_value) = env::var(substitution_name) {
-        output.push_str(&environment_value);
+type Chars<'a> = Peekable<CharIndices<'a>>;
+
+/// Decodes the escape sequence `\c` inside double quotes, `index` being the
+/// byte index of `c`. The digits of `\x` and `\u` escapes are read from `chars`.
+///
+/// Returns `None` for unknown escapes, and the byte index of the offending
+/// character for invalid ones.
+fn weak_escape(
+    index: usize,
+    c: char,
+    chars: &mut Chars,
+) -> std::result::Result<Option<char>, usize> {
+    let decoded = match c {
+        '\\' | '\'' | '"' | '$' | ' ' => c,
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        'a' => '\x07',
+        'b' => '\x08',
+        'f' => '\x0c',
+        'v' => '\x0b',
+        'x' => char::from(hex_digits(index, chars, 2)? as u8),
+        'u' => unicode_escape(index, chars)?,
+        _ => return Ok(None),
+    };
+
+    // environment variables cannot hold NUL characters
+    if decoded == '\0' {
+        Err(index)
+    } else {
+        Ok(Some(decoded))
+    }
+}
+
+/// Decodes the digits of a `\uXXXX` or `\u{X...}` escape, combining UTF-16
+/// surrogate pairs written as two `\uXXXX` escapes.
+fn unicode_escape(index: usize, chars: &mut Chars) -> std::result::Result<char, usize> {
+    let code = if let Some(&(_, '{')) = chars.peek() {
+        chars.next();
+        let mut code = 0;
+        let mut digits = 0;
+        loop {
+            match chars.next() {
+                Some((_, '}')) if digits > 0 => break,
+                Some((index, c)) => match c.to_digit(16) {
+                    Some(digit) if digits < 6 => {
+                        code = code * 16 + digit;
+                        digits += 1;
+                    }
+                    _ => return Err(index),
+                },
+                None => return Err(index),
+            }
+        }
+        code
     } else {
-        let stored_value = substitution_data
-            .get(substitution_name)
-            .unwrap_or(&None)
-            .to_owned();
-        output.push_str(&stored_value.unwrap_or_default());
+        let code = hex_digits(index, chars, 4)?;
+        if (0xD800..0xDC00).contains(&code) {
+            // a high surrogate must be followed by a `\uXXXX` low surrogate
+            let mut lookahead = chars.clone();
+            match (lookahead.next(), lookahead.next()) {
+                (Some((_, '\\')), Some((index, 'u'))) => {
+                    let low = hex_digits(index, &mut lookahead, 4)?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(index);
+                    }
+                    *chars = lookahead;
+                    0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)
+                }
+                _ => return Err(index),
+            }
+        } else {
+            code
+        }
+    };
+
+    char::from_u32(code).ok_or(index)
+}
+
+/// Reads `count` hexadecimal digits from `chars`, returning the index of the
+/// first character that is not one, or `index` if the input ends early.
+fn hex_digits(index: usize, chars: &mut Chars, count: usize) -> std::result::Result<u32, usize> {
+    let mut code = 0;
+    for _ in 0..count {
+        match chars.peek() {
+            Some(&(index, c)) => match c.to_digit(16) {
+                Some(digit) => {
+                    code = code * 16 + digit;
+                    chars.next();
+                }
+                None => return Err(index),
+            },
+            None => return Err(index),
+        }
+    }
+    Ok(code)
+}
+
+/// The header of a heredoc value, such as `<<EOF`.
+pub struct Heredoc {
+    delimiter: String,
+    /// With `<<-`, the common indentation is removed and the terminator may be indented.
+    strip_indent: bool,
+    /// With a quoted delimiter, the body is taken literally.
+    literal: bool,
+}
+
+/// Parses the heredoc header starting the value of the definition `line`, if any.
+///
+/// The value is found after the first `=`.
+pub fn heredoc(line: &str) -> Option<Heredoc> {
+    let header = line.lines().next()?;
+    heredoc_value(header.split_once('=')?.1)
+}
+
+/// Parses the heredoc header starting `value`, if any.
+pub fn heredoc_value(value: &str) -> Option<Heredoc> {
+    let value = value.lines().next()?.trim().strip_prefix("<<")?;
+
+    let (strip_indent, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let (literal, delimiter) = match value.chars().next() {
+        Some(quote @ '\'') | Some(quote @ '"') => {
+            (true, value.strip_prefix(quote)?.strip_suffix(quote)?)
+        }
+        _ => (false, value),
+    };
+
+    if delimiter.is_empty() || !delimiter.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    Some(Heredoc {
+        delimiter: delimiter.to_owned(),
+        strip_indent,
+        literal,
+    })
+}
+
+impl Heredoc {
+    /// Returns `true` if `line` ends the heredoc.
+    pub fn is_terminator(&self, line: &str) -> bool {
+        let line = if self.strip_indent {
+            line.trim()
+        } else {
+            line.trim_end()
+        };
+        line == self.delimiter
+    }
+
+    /// Parses the lines between the header and the terminator of `input`.
+    fn parse_body(&self, input: &str, substitute: bool) -> Template {
+        let mut lines: Vec<&str> = input.lines().skip(1).collect();
+        if lines.last().map_or(false, |line| self.is_terminator(line)) {
+            lines.pop();
+        }
+
+        if self.strip_indent {
+            let indent = lines
+                .iter()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+                .min()
+                .unwrap_or(0);
+            for line in &mut lines {
+                *line = line.get(indent..).unwrap_or("");
+            }
+        }
+        let body = lines.join("\n");
+
+        if self.literal || !substitute {
+            vec![Segment::Literal(body)]
+        } else {
+            parse_word(&body, false)
+        }
+    }
+}
+
+/// Returns the length of the contents of a `${...}` block, up to its closing brace.
+///
+/// `content` starts after the opening brace. Nested `${...}` blocks are skipped
+/// and a backslash escapes the following character.
+fn braced_len(content: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut escaped = false;
+    let mut dollar = false;
+
+    for (index, c) in content.char_indices() {
+        if escaped {
+            escaped = false;
+        } else {
+            match c {
+                '\\' => escaped = true,
+                '{' if dollar => depth += 1,
+                '}' if depth == 0 => return Some(index),
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        dollar = !escaped && c == '$';
+    }
+    None
+}
+
+/// Parses the contents of a `${...}` block.
+///
+/// Contents without a valid name and operator are used as the name as a whole.
+fn parse_expansion(content: &str) -> Expansion {
+    let name_len = content
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(content.len());
+    let (name, rest) = content.split_at(name_len);
+    let (colon, rest) = match rest.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
     };
+    let operator = match rest.chars().next() {
+        Some('-') => Some(Operator::Default),
+        Some('=') => Some(Operator::Assign),
+        Some('?') => Some(Operator::Error),
+        Some('+') => Some(Operator::Alternative),
+        _ => None,
+    };
+
+    match operator {
+        Some(operator) if !name.is_empty() => Expansion {
+            name: name.to_owned(),
+            modifier: Some(Modifier {
+                operator,
+                colon,
+                word: parse_word(&rest[1..], true),
+            }),
+        },
+        _ => Expansion {
+            name: content.to_owned(),
+            modifier: None,
+        },
+    }
+}
+
+/// Parses the word of an expansion operator, such as `default` in `${NAME:-default}`,
+/// or the body of a heredoc.
+///
+/// Quotes are kept as they are. A backslash escapes the following character,
+/// or only a `$` unless `escape_all` is set.
+fn parse_word(word: &str, escape_all: bool) -> Template {
+    let mut output = Template::new();
+    let mut chars = word.char_indices().peekable();
+
+    while let Some((byte_index, c)) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some(&(_, next)) if escape_all || next == '$' => {
+                    push_literal(&mut output, next);
+                    chars.next();
+                }
+                _ => push_literal(&mut output, c),
+            },
+            '$' => {
+                if let Some(&(_, '{')) = chars.peek() {
+                    let content = &word[byte_index + 2..];
+                    // nested blocks were already matched by `braced_len`
+                    let len = braced_len(content).unwrap_or(content.len());
+                    output.push(Segment::Var(parse_expansion(&content[..len])));
+                    while let Some(&(index, _)) = chars.peek() {
+                        if index > byte_index + 2 + len {
+                            break;
+                        }
+                        chars.next();
+                    }
+                } else {
+                    let mut name = String::new();
+                    while let Some(&(_, c)) = chars.peek() {
+                        if !c.is_alphanumeric() {
+                            break;
+                        }
+                        name.push(c);
+                        chars.next();
+                    }
+                    push_var(&mut output, name);
+                }
+            }
+            _ => push_literal(&mut output, c),
+        }
+    }
+    output
 }
 
 #[cfg(test)]
@@ -394,54 +1113,234 @@ KEY7="line 1\nline 2"
     }
 
     #[test]
-    fn test_parse_value_escapes_invalid() {
+    fn test_parse_value_weak_quote_escapes() {
         let actual_iter = Iter::new(
             r#"
-KEY=my uncool value
-KEY2="why
-KEY3='please stop''
-KEY4=h\8u
+KEY="tab\there\r\n"
+KEY2="\a\b\f\v"
+KEY3="\x41\x7e\xe9"
+KEY4="\u00e9\u{1F600}\uD83D\uDE00"
+KEY5="C:\Users\data\d+"
+KEY6=">\$HOME<"
 "#
             .as_bytes(),
         );
 
-        for actual in actual_iter {
-            assert!(actual.is_err());
-        }
-    }
-}
-
-#[cfg(test)]
-mod variable_substitution_tests {
-    use crate::iter::Iter;
-    use std::env;
-
-    fn assert_parsed_string(input_string: &str, expected_parse_result: Vec<(&str, &str)>) {
-        let actual_iter = Iter::new(input_string.as_bytes());
-        let expected_count = &expected_parse_result.len();
-
-        let expected_iter = expected_parse_result
-            .into_iter()
-            .map(|(key, value)| (key.to_string(), value.to_string()));
+        let expected_iter = vec![
+            ("KEY", "tab\there\r\n"),
+            ("KEY2", "\x07\x08\x0c\x0b"),
+            ("KEY3", "A~\u{e9}"),
+            ("KEY4", "\u{e9}\u{1F600}\u{1F600}"),
+            ("KEY5", r"C:\Users\data\d+"),
+            ("KEY6", ">$HOME<"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()));
 
         let mut count = 0;
         for (expected, actual) in expected_iter.zip(actual_iter) {
-            assert!(actual.is_ok());
             assert_eq!(expected, actual.unwrap());
             count += 1;
         }
-
-        assert_eq!(count, *expected_count);
+        assert_eq!(count, 6);
     }
 
     #[test]
-    fn variable_in_parenthesis_surrounded_by_quotes() {
-        assert_parsed_string(
+    fn test_parse_value_weak_quote_escapes_invalid() {
+        let actual_iter = Iter::new(
             r#"
-            KEY=test
-            KEY1="${KEY}"
-            "#,
-            vec![("KEY", "test"), ("KEY1", "test")],
+KEY="\x4"
+KEY2="\xZZ"
+KEY3="\u12"
+KEY4="\u{}"
+KEY5="\u{1234567}"
+KEY6="\u{110000}"
+KEY7="\uD83D"
+KEY8="\uDE00"
+KEY9="\x00"
+KEY10="\u{0}"
+"#
+            .as_bytes(),
+        );
+
+        let mut count = 0;
+        for actual in actual_iter {
+            assert!(actual.is_err());
+            count += 1;
+        }
+        assert_eq!(count, 10);
+    }
+
+    #[test]
+    fn test_parse_value_backticks() {
+        let actual_iter = Iter::new(
+            r#"
+KEY=`it's "quoted"`
+KEY2=`$HOME \n ${USER}`
+KEY3=`line 1
+line 2`
+KEY4=pre`'"`post #comment
+"#
+            .as_bytes(),
+        );
+
+        let expected_iter = vec![
+            ("KEY", r#"it's "quoted""#),
+            ("KEY2", r"$HOME \n ${USER}"),
+            ("KEY3", "line 1\nline 2"),
+            ("KEY4", r#"pre'"post"#),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+
+        let mut count = 0;
+        for (expected, actual) in expected_iter.zip(actual_iter) {
+            assert_eq!(expected, actual.unwrap());
+            count += 1;
+        }
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn test_parse_line_continuation() {
+        let actual_iter = Iter::new(
+            "KEY=first\\\n    second\\\r\n\tthird\nKEY2=\"a\\\n  b\"\nKEY3=last\\\n".as_bytes(),
+        );
+
+        let expected_iter = vec![
+            ("KEY", "firstsecondthird"),
+            ("KEY2", "a  b"),
+            ("KEY3", "last"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+
+        let mut count = 0;
+        for (expected, actual) in expected_iter.zip(actual_iter) {
+            assert_eq!(expected, actual.unwrap());
+            count += 1;
+        }
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_parse_heredoc() {
+        let actual_iter = Iter::new(
+            r#"
+HOST=localhost
+CERT=<<EOF
+-----BEGIN CERTIFICATE-----
+  "quoted" 'and' \n $HOST \$HOST
+-----END CERTIFICATE-----
+EOF
+JSON=<<-END
+    {
+      "host": "${HOST}"
+    }
+    END
+LITERAL=<<'EOF'
+$HOST
+EOF
+export EMPTY=<<EOF
+EOF
+AFTER=1
+"#
+            .as_bytes(),
+        );
+
+        let expected_iter = vec![
+            ("HOST", "localhost"),
+            (
+                "CERT",
+                "-----BEGIN CERTIFICATE-----\n  \"quoted\" 'and' \\n localhost $HOST\n-----END CERTIFICATE-----",
+            ),
+            ("JSON", "{\n  \"host\": \"localhost\"\n}"),
+            ("LITERAL", "$HOST"),
+            ("EMPTY", ""),
+            ("AFTER", "1"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+
+        let mut count = 0;
+        for (expected, actual) in expected_iter.zip(actual_iter) {
+            assert_eq!(expected, actual.unwrap());
+            count += 1;
+        }
+        assert_eq!(count, 6);
+    }
+
+    #[test]
+    fn test_parse_heredoc_marker_inside_value() {
+        let result: Result<Vec<(String, String)>> =
+            Iter::new("KEY=a=<<EOF\nQUOTED=\"x\"=<<EOF\nNEXT=1\n".as_bytes()).collect();
+        let expected = vec![("KEY", "a=<<EOF"), ("QUOTED", "x=<<EOF"), ("NEXT", "1")]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(result.unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_heredoc_unterminated() {
+        let result: Result<Vec<(String, String)>> =
+            Iter::new("KEY=<<EOF\nbody\nEOF2\n".as_bytes()).collect();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_value_escapes_invalid() {
+        let actual_iter = Iter::new(
+            r#"
+KEY=my uncool value
+KEY2="why
+KEY3='please stop''
+KEY5=`unterminated
+KEY4=h\8u
+"#
+            .as_bytes(),
+        );
+
+        for actual in actual_iter {
+            assert!(actual.is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod variable_substitution_tests {
+    use crate::iter::Iter;
+    use crate::loader::Precedence;
+    use crate::resolver::Isolated;
+    use std::collections::HashMap;
+    use std::env;
+
+    fn assert_parsed_string(input_string: &str, expected_parse_result: Vec<(&str, &str)>) {
+        let actual_iter = Iter::new(input_string.as_bytes());
+        let expected_count = &expected_parse_result.len();
+
+        let expected_iter = expected_parse_result
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()));
+
+        let mut count = 0;
+        for (expected, actual) in expected_iter.zip(actual_iter) {
+            assert!(actual.is_ok());
+            assert_eq!(expected, actual.unwrap());
+            count += 1;
+        }
+
+        assert_eq!(count, *expected_count);
+    }
+
+    #[test]
+    fn variable_in_parenthesis_surrounded_by_quotes() {
+        assert_parsed_string(
+            r#"
+            KEY=test
+            KEY1="${KEY}"
+            "#,
+            vec![("KEY", "test"), ("KEY1", "test")],
         );
     }
 
@@ -450,6 +1349,24 @@ mod variable_substitution_tests {
         assert_parsed_string(r#"KEY=">$KEY1<>${KEY2}<""#, vec![("KEY", "><><")]);
     }
 
+    #[test]
+    fn unbraced_variable_name_ends_before_underscore() {
+        assert_parsed_string(
+            r#"
+    UNBRACED=foo
+    UNBRACED_NAME=bar
+    KEY1=$UNBRACED_NAME
+    KEY2=${UNBRACED_NAME}
+    "#,
+            vec![
+                ("UNBRACED", "foo"),
+                ("UNBRACED_NAME", "bar"),
+                ("KEY1", "foo_NAME"),
+                ("KEY2", "bar"),
+            ],
+        );
+    }
+
     #[test]
     fn do_not_substitute_variables_with_dollar_escaped() {
         assert_parsed_string(
@@ -539,6 +1456,128 @@ mod variable_substitution_tests {
         );
     }
 
+    #[test]
+    fn substitute_variable_dotenv_overrides_env_variable_with_file_precedence() {
+        env::set_var("KEY12", "test_user_env");
+
+        let actual: Vec<_> = Iter::new(
+            r#"
+    KEY=">${KEY12}<"
+    KEY12=test_user
+    KEY1=">${KEY12}<"
+    "#
+            .as_bytes(),
+        )
+        .substitution_precedence(Precedence::File)
+        .map(|result| result.unwrap().1)
+        .collect();
+
+        assert_eq!(actual, vec![">test_user_env<", "test_user", ">test_user<"]);
+    }
+
+    #[test]
+    fn substitute_variable_from_resolver() {
+        env::set_var("KEY13", "test_user_env");
+        let mut resolver = HashMap::new();
+        resolver.insert("KEY14".to_string(), "test_user_resolver".to_string());
+
+        let actual: Vec<_> = Iter::new(
+            r#"
+    KEY=">${KEY13}<>${KEY14}<"
+    KEY13=test_user
+    KEY1=">${KEY13}<"
+    "#
+            .as_bytes(),
+        )
+        .resolver(resolver)
+        .map(|result| result.unwrap().1)
+        .collect();
+
+        assert_eq!(
+            actual,
+            vec!["><>test_user_resolver<", "test_user", ">test_user<"]
+        );
+    }
+
+    #[test]
+    fn isolated_resolver_ignores_env_variables() {
+        env::set_var("KEY15", "test_user_env");
+
+        assert_eq!(
+            Iter::new("KEY=>$KEY15<".as_bytes())
+                .resolver(Isolated)
+                .next()
+                .unwrap()
+                .unwrap()
+                .1,
+            "><"
+        );
+    }
+
+    #[test]
+    fn no_substitution() {
+        let actual: Vec<_> = Iter::new(
+            r#"
+    KEY=value
+    KEY1=$KEY
+    KEY2="${KEY:-default}"
+    KEY3=\$KEY
+    "#
+            .as_bytes(),
+        )
+        .no_substitution()
+        .map(|result| result.unwrap().1)
+        .collect();
+
+        assert_eq!(actual, vec!["value", "$KEY", "${KEY:-default}", "$KEY"]);
+    }
+
+    #[test]
+    fn forward_references() {
+        let actual: Vec<_> = Iter::new(
+            r#"
+    URL=${SCHEME}://${HOST_FWD}:$PORT
+    HOST_FWD=${NAME}.example.com
+    NAME=api
+    PORT=${PORT_FWD:-8080}
+    SCHEME=https
+    "#
+            .as_bytes(),
+        )
+        .forward_references()
+        .map(|result| result.unwrap())
+        .collect();
+
+        assert_eq!(
+            actual,
+            vec![
+                ("URL".into(), "https://api.example.com:8080".into()),
+                ("HOST_FWD".into(), "api.example.com".into()),
+                ("NAME".into(), "api".into()),
+                ("PORT".into(), "8080".into()),
+                ("SCHEME".into(), "https".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn forward_references_prefer_earlier_definitions() {
+        let actual: Vec<_> = Iter::new(
+            r#"
+    KEY1=${KEY2}-${KEY3}
+    KEY3=first
+    KEY4=${KEY3}
+    KEY3=$KEY3-second
+    "#
+            .as_bytes(),
+        )
+        .forward_references()
+        .map(|result| result.unwrap().1)
+        .collect();
+
+        assert_eq!(actual, vec!["-first", "first", "first", "first-second"]);
+    }
+
     #[test]
     fn consequent_substitutions() {
         assert_parsed_string(
@@ -565,12 +1604,140 @@ mod variable_substitution_tests {
             vec![("KEY2", "_2"), ("KEY", "><>_2<")],
         );
     }
+
+    #[test]
+    fn variable_followed_by_quote_or_escape() {
+        assert_parsed_string(
+            r#"
+    KEY1=test
+    KEY2="$KEY1"
+    KEY3=$KEY1'_x'
+    KEY4=$KEY1\ x
+    KEY5=$KEY1 # comment
+    "#,
+            vec![
+                ("KEY1", "test"),
+                ("KEY2", "test"),
+                ("KEY3", "test_x"),
+                ("KEY4", "test x"),
+                ("KEY5", "test"),
+            ],
+        );
+    }
+
+    #[test]
+    fn default_operators() {
+        assert_parsed_string(
+            r#"
+    SET=value
+    EMPTY=
+    KEY1=${SET:-default}
+    KEY2=${EMPTY:-default}
+    KEY3=${EMPTY-default}
+    KEY4=${UNSET_OP1-default}
+    KEY5="${UNSET_OP1:-two words}"
+    "#,
+            vec![
+                ("SET", "value"),
+                ("EMPTY", ""),
+                ("KEY1", "value"),
+                ("KEY2", "default"),
+                ("KEY3", ""),
+                ("KEY4", "default"),
+                ("KEY5", "two words"),
+            ],
+        );
+    }
+
+    #[test]
+    fn alternative_operators() {
+        assert_parsed_string(
+            r#"
+    SET=value
+    EMPTY=
+    KEY1=${SET:+alt}
+    KEY2=${EMPTY:+alt}
+    KEY3=${EMPTY+alt}
+    KEY4=${UNSET_OP2+alt}
+    "#,
+            vec![
+                ("SET", "value"),
+                ("EMPTY", ""),
+                ("KEY1", "alt"),
+                ("KEY2", ""),
+                ("KEY3", "alt"),
+                ("KEY4", ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn assign_operators() {
+        assert_parsed_string(
+            r#"
+    EMPTY=
+    KEY1=${UNSET_OP3:=assigned}
+    KEY2=${UNSET_OP3}
+    KEY3=${EMPTY=ignored}
+    KEY4=${EMPTY:=assigned}
+    KEY5=$EMPTY
+    "#,
+            vec![
+                ("EMPTY", ""),
+                ("KEY1", "assigned"),
+                ("KEY2", "assigned"),
+                ("KEY3", ""),
+                ("KEY4", "assigned"),
+                ("KEY5", "assigned"),
+            ],
+        );
+    }
+
+    #[test]
+    fn nested_expansions_in_word() {
+        assert_parsed_string(
+            r#"
+    HOST=localhost
+    KEY1=${UNSET_OP4:-http://${HOST}:${PORT_OP4:-8080}}
+    KEY2=${UNSET_OP4:-$HOST\}}
+    KEY3=${UNSET_OP4:+${UNSET_OP5:?unused}}
+    "#,
+            vec![
+                ("HOST", "localhost"),
+                ("KEY1", "http://localhost:8080"),
+                ("KEY2", "localhost}"),
+                ("KEY3", ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn required_variable_set() {
+        assert_parsed_string(
+            r#"
+    SET=value
+    KEY1=${SET:?must be set}
+    KEY2=${SET?must be set}
+    "#,
+            vec![("SET", "value"), ("KEY1", "value"), ("KEY2", "value")],
+        );
+    }
 }
 
 #[cfg(test)]
 mod error_tests {
-    use crate::errors::Error::LineParse;
+    use crate::errors::Error::{CyclicSubstitution, Parse, Substitution};
+    use crate::errors::{ParseError, ParseErrorKind, Result};
     use crate::iter::Iter;
+    use crate::parse::{KeyPolicy, UndefinedVars};
+
+    fn parse_error(item: &Result<(String, String)>) -> &ParseError {
+        match item {
+            Err(Parse(err)) => err,
+            Err(err) => panic!("expected a parse error, got: {}", err),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
 
     #[test]
     fn should_not_parse_unfinished_substitutions() {
@@ -596,14 +1763,133 @@ mod error_tests {
             panic!("Expected the first value to be parsed")
         }
 
-        if let Err(LineParse(second_value, index)) = &parsed_values[1] {
-            assert_eq!(second_value, wrong_value);
-            assert_eq!(*index, wrong_value.len() - 1)
+        let err = parse_error(&parsed_values[1]);
+        assert!(matches!(
+            err.kind(),
+            ParseErrorKind::UnterminatedSubstitution
+        ));
+        assert_eq!((err.line(), err.column()), (3, 11));
+        assert_eq!(err.line_text(), format!("    KEY1={}", wrong_value));
+    }
+
+    #[test]
+    fn should_fail_on_required_variable() {
+        let parsed_values: Vec<_> = Iter::new(
+            r#"
+    EMPTY=
+    KEY1=${EMPTY?empty is fine}
+    KEY2=${EMPTY:?must not be empty}
+    KEY3=${UNSET_REQUIRED?}
+    "#
+            .as_bytes(),
+        )
+        .collect();
+
+        assert_eq!(parsed_values.len(), 4);
+        assert!(parsed_values[1].is_ok());
+
+        if let Err(Substitution(err)) = &parsed_values[2] {
+            assert_eq!(err.var(), "EMPTY");
+            assert_eq!(err.key(), "KEY2");
+            assert_eq!(err.line(), 4);
+            assert_eq!(err.message(), Some("must not be empty"));
+        } else {
+            panic!("Expected the third value not to be parsed")
+        }
+
+        if let Err(Substitution(err)) = &parsed_values[3] {
+            assert_eq!(err.var(), "UNSET_REQUIRED");
+            assert_eq!(err.message(), None);
         } else {
-            panic!("Expected the second value not to be parsed")
+            panic!("Expected the fourth value not to be parsed")
         }
     }
 
+    #[test]
+    fn should_fail_on_undefined_variable_in_strict_mode() {
+        let parsed_values: Vec<_> = Iter::new(
+            r#"
+    EMPTY=
+    KEY1=$EMPTY${UNDEFINED_STRICT:-default}
+    KEY2=">${UNDEFINED_STRICT}<"
+    "#
+            .as_bytes(),
+        )
+        .undefined_vars(UndefinedVars::Error)
+        .collect();
+
+        assert_eq!(parsed_values.len(), 3);
+        assert!(parsed_values[1].is_ok());
+
+        if let Err(Substitution(err)) = &parsed_values[2] {
+            assert_eq!(err.var(), "UNDEFINED_STRICT");
+            assert_eq!(err.key(), "KEY2");
+            assert_eq!(err.line(), 4);
+            assert_eq!(err.message(), None);
+        } else {
+            panic!("Expected the third value not to be parsed")
+        }
+    }
+
+    #[test]
+    fn should_name_unbraced_variable_up_to_underscore() {
+        let parsed_values: Vec<_> = Iter::new("KEY=$UNDEFINED_UNBRACED".as_bytes())
+            .undefined_vars(UndefinedVars::Error)
+            .collect();
+
+        if let Err(Substitution(err)) = &parsed_values[0] {
+            assert_eq!(err.var(), "UNDEFINED");
+        } else {
+            panic!("Expected the value not to be parsed")
+        }
+    }
+
+    #[test]
+    fn should_collect_undefined_variables() {
+        let mut iter = Iter::new(
+            r#"
+    KEY1=${UNDEFINED_COLLECT}
+    KEY2=$KEY1${UNDEFINED_COLLECT:-default}
+    "#
+            .as_bytes(),
+        )
+        .undefined_vars(UndefinedVars::Collect);
+
+        assert_eq!(iter.by_ref().filter(|result| result.is_ok()).count(), 2);
+        let unresolved = iter.unresolved();
+        assert_eq!(unresolved.len(), 1);
+        assert_eq!(unresolved[0].var(), "UNDEFINED_COLLECT");
+        assert_eq!(unresolved[0].key(), "KEY1");
+        assert_eq!(unresolved[0].line(), 2);
+    }
+
+    #[test]
+    fn should_fail_on_cyclic_substitution() {
+        let parsed_values: Vec<_> = Iter::new(
+            r#"
+    A=$B
+    B=${C:-default}
+    C=$A
+    D=ok
+    E=$A
+    "#
+            .as_bytes(),
+        )
+        .forward_references()
+        .collect();
+
+        assert_eq!(parsed_values.len(), 5);
+        let cycle = vec!["A".to_string(), "B".into(), "C".into(), "A".into()];
+        for value in &parsed_values[..3] {
+            match value {
+                Err(CyclicSubstitution(actual)) => assert_eq!(actual, &cycle),
+                _ => panic!("Expected the cycle not to be parsed"),
+            }
+        }
+        assert_eq!(parsed_values[3].as_ref().unwrap().1, "ok");
+        assert_eq!(parsed_values[4].as_ref().unwrap().1, "");
+    }
+
     #[test]
     fn should_not_allow_dot_as_first_character_of_key() {
         let wrong_key_value = ".Key=VALUE";
@@ -612,14 +1898,77 @@ mod error_tests {
 
         assert_eq!(parsed_values.len(), 1);
 
-        if let Err(LineParse(second_value, index)) = &parsed_values[0] {
-            assert_eq!(second_value, wrong_key_value);
-            assert_eq!(*index, 0)
-        } else {
-            panic!("Expected the second value not to be parsed")
+        match parse_error(&parsed_values[0]).kind() {
+            ParseErrorKind::InvalidKey(err) => assert_eq!(err.key(), ".Key"),
+            kind => panic!("unexpected error: {}", kind),
         }
     }
 
+    #[test]
+    fn should_apply_key_policy() {
+        let input = "A.B=1\nC-D=2\nÉTÉ=3\nlower=4\n";
+        let keys = |policy| -> Vec<_> {
+            Iter::new(input.as_bytes())
+                .key_policy(policy)
+                .map(|item| match item {
+                    Ok((key, _)) => Ok(key),
+                    Err(Parse(err)) => match err.kind() {
+                        ParseErrorKind::InvalidKey(err) => Err(err.key().to_owned()),
+                        kind => panic!("unexpected error: {}", kind),
+                    },
+                    Err(err) => panic!("unexpected error: {}", err),
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            keys(KeyPolicy::Default),
+            vec![
+                Ok("A.B".into()),
+                Err("C-D".into()),
+                Err("ÉTÉ".into()),
+                Ok("lower".into())
+            ]
+        );
+        assert_eq!(
+            keys(KeyPolicy::Posix),
+            vec![
+                Err("A.B".into()),
+                Err("C-D".into()),
+                Err("ÉTÉ".into()),
+                Ok("lower".into())
+            ]
+        );
+        assert_eq!(
+            keys(KeyPolicy::Permissive),
+            vec![
+                Ok("A.B".into()),
+                Ok("C-D".into()),
+                Ok("ÉTÉ".into()),
+                Ok("lower".into())
+            ]
+        );
+        assert_eq!(
+            keys(KeyPolicy::Custom(|key| key
+                .chars()
+                .all(|c| !c.is_lowercase()))),
+            vec![
+                Ok("A.B".into()),
+                Ok("C-D".into()),
+                Ok("ÉTÉ".into()),
+                Err("lower".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn should_not_report_invalid_key_without_equal_sign() {
+        let parsed_values: Vec<_> = Iter::new("C-D 2".as_bytes()).collect();
+        let err = parse_error(&parsed_values[0]);
+        assert!(matches!(err.kind(), ParseErrorKind::MissingEquals));
+        assert_eq!(err.column(), 4);
+    }
+
     #[test]
     fn should_not_parse_illegal_format() {
         let wrong_format = r"<><><>";
@@ -627,12 +1976,9 @@ mod error_tests {
 
         assert_eq!(parsed_values.len(), 1);
 
-        if let Err(LineParse(wrong_value, index)) = &parsed_values[0] {
-            assert_eq!(wrong_value, wrong_format);
-            assert_eq!(*index, 0)
-        } else {
-            panic!("Expected the second value not to be parsed")
-        }
+        let err = parse_error(&parsed_values[0]);
+        assert!(matches!(err.kind(), ParseErrorKind::MissingEquals));
+        assert_eq!((err.line(), err.column()), (1, 7));
     }
 
     #[test]
@@ -643,11 +1989,97 @@ mod error_tests {
 
         assert_eq!(parsed_values.len(), 1);
 
-        if let Err(LineParse(wrong_value, index)) = &parsed_values[0] {
-            assert_eq!(wrong_value, wrong_escape);
-            assert_eq!(*index, wrong_escape.find('\\').unwrap() + 1)
-        } else {
-            panic!("Expected the second value not to be parsed")
+        let err = parse_error(&parsed_values[0]);
+        assert!(matches!(err.kind(), ParseErrorKind::InvalidEscape));
+        assert_eq!(
+            err.column(),
+            "VALUE=".len() + wrong_escape.find('\\').unwrap() + 2
+        );
+    }
+
+    #[test]
+    fn should_locate_errors_in_multi_line_values() {
+        let parsed_values: Vec<_> =
+            Iter::new("A=1\nB=\"one\n  \\xZZ\"\nC='unterminated\nD=4\n".as_bytes())
+                .source_name(".env")
+                .collect();
+        assert_eq!(parsed_values.len(), 3);
+
+        let err = parse_error(&parsed_values[1]);
+        assert!(matches!(err.kind(), ParseErrorKind::InvalidEscape));
+        assert_eq!(err.source_name(), Some(".env"));
+        assert_eq!((err.line(), err.column(), err.start_line()), (3, 5, 2));
+        assert_eq!(err.line_text(), "  \\xZZ\"");
+
+        let err = parse_error(&parsed_values[2]);
+        assert!(matches!(err.kind(), ParseErrorKind::UnterminatedQuote));
+        assert_eq!((err.line(), err.column(), err.start_line()), (4, 3, 4));
+    }
+
+    #[test]
+    fn should_recover_from_unterminated_quotes_when_lenient() {
+        let input = "A='one\nB=2\nC=\"x\nD=\"4\" tail\nE=5\nF=\"\\xZZ\n\"\nG=7\n";
+        for forward_references in [false, true] {
+            let mut iter = Iter::new(input.as_bytes()).lenient();
+            if forward_references {
+                iter = iter.forward_references();
+            }
+            let parsed_values: Vec<_> = iter.collect();
+            assert_eq!(parsed_values.len(), 7);
+
+            let err = parse_error(&parsed_values[0]);
+            assert!(matches!(err.kind(), ParseErrorKind::UnterminatedQuote));
+            assert_eq!((err.line(), err.column()), (1, 3));
+            assert_eq!(parsed_values[1].as_ref().unwrap().1, "2");
+
+            let err = parse_error(&parsed_values[2]);
+            assert!(matches!(err.kind(), ParseErrorKind::UnterminatedQuote));
+            assert_eq!(err.line(), 3);
+            let err = parse_error(&parsed_values[3]);
+            assert!(matches!(err.kind(), ParseErrorKind::TrailingCharacters));
+            assert_eq!((err.line(), err.column()), (4, 7));
+            assert_eq!(parsed_values[4].as_ref().unwrap().1, "5");
+
+            // other errors in multi-line values are not split
+            let err = parse_error(&parsed_values[5]);
+            assert!(matches!(err.kind(), ParseErrorKind::InvalidEscape));
+            assert_eq!((err.line(), err.start_line()), (6, 6));
+            assert_eq!(
+                parsed_values[6].as_ref().unwrap(),
+                &("G".to_string(), "7".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn should_not_recover_unless_lenient() {
+        let parsed_values: Vec<_> = Iter::new("A='one\nB=2\n".as_bytes()).collect();
+        assert_eq!(parsed_values.len(), 1);
+    }
+
+    #[test]
+    fn should_report_unterminated_heredoc() {
+        let parsed_values: Vec<_> = Iter::new("A=1\nCERT=<<EOF\nbody\n".as_bytes()).collect();
+
+        let err = parse_error(&parsed_values[1]);
+        assert!(matches!(err.kind(), ParseErrorKind::UnterminatedHeredoc));
+        assert_eq!((err.line(), err.column()), (2, 6));
+    }
+
+    #[test]
+    fn should_report_trailing_characters() {
+        let parsed_values: Vec<_> = Iter::new("KEY=a b".as_bytes()).collect();
+
+        let err = parse_error(&parsed_values[0]);
+        assert!(matches!(err.kind(), ParseErrorKind::TrailingCharacters));
+        assert_eq!(err.column(), 7);
+    }
+
+    #[test]
+    fn should_parse_whitespace_and_backslash_at_end_of_input() {
+        for input in ["KEY=a ", "KEY=a\\"] {
+            let parsed_values: Vec<_> = Iter::new(input.as_bytes()).collect();
+            assert_eq!(parsed_values[0].as_ref().unwrap().1, "a", "{:?}", input);
         }
     }
 }
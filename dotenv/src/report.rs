@@ -0,0 +1,246 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::errors::Error;
+
+/// Where a variable was defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// The process environment, as it was before loading.
+    Env,
+    /// A file at the given path.
+    Path(PathBuf),
+    /// A reader or in-memory string, identified by its
+    /// [`label`](crate::EnvSource::label).
+    Reader(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Env => write!(fmt, "process environment"),
+            Origin::Path(path) => write!(fmt, "{}", path.display()),
+            Origin::Reader(label) => write!(fmt, "{}", label),
+        }
+    }
+}
+
+/// What happened to a definition when the sources were loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The variable was not set before, and this definition was loaded.
+    Set,
+    /// This definition was loaded, overriding the existing environment variable.
+    Overridden,
+    /// The existing environment variable was preserved, and this definition was skipped.
+    Skipped,
+    /// A later definition of the same variable was used instead.
+    Superseded,
+}
+
+/// A single definition of a variable in one of the loaded sources.
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub(crate) key: String,
+    pub(crate) value: String,
+    pub(crate) raw: String,
+    pub(crate) origin: Origin,
+    pub(crate) line: usize,
+    pub(crate) status: Status,
+}
+
+impl Definition {
+    /// The name of the variable.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The value after unquoting and substitution.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The value as written in the source, before unquoting and substitution.
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// The source the definition was read from.
+    pub fn origin(&self) -> &Origin {
+        &self.origin
+    }
+
+    /// The 1-based line the definition starts on.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// What happened to the definition when loading.
+    pub fn status(&self) -> Status {
+        self.status
+    }
+}
+
+/// A reference to a variable that was not defined, recorded with
+/// [`UndefinedVars::Collect`](crate::UndefinedVars::Collect).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unresolved {
+    pub(crate) var: String,
+    pub(crate) key: String,
+    pub(crate) line: usize,
+}
+
+impl Unresolved {
+    /// The name of the variable that was not defined.
+    pub fn var(&self) -> &str {
+        &self.var
+    }
+
+    /// The key whose value refers to the variable.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The 1-based line the key is defined on.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+/// A variable defined again after an earlier definition, recorded with
+/// [`DuplicateKeys::Warn`](crate::DuplicateKeys::Warn) or returned as
+/// [`Error::DuplicateKey`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate {
+    pub(crate) key: String,
+    pub(crate) previous_origin: Origin,
+    pub(crate) previous_line: usize,
+    pub(crate) origin: Origin,
+    pub(crate) line: usize,
+}
+
+impl Duplicate {
+    /// The name of the variable.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The source of the earlier definition.
+    pub fn previous_origin(&self) -> &Origin {
+        &self.previous_origin
+    }
+
+    /// The 1-based line of the earlier definition.
+    pub fn previous_line(&self) -> usize {
+        self.previous_line
+    }
+
+    /// The source of the duplicate definition.
+    pub fn origin(&self) -> &Origin {
+        &self.origin
+    }
+
+    /// The 1-based line of the duplicate definition.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "duplicate key `{}`: defined on line {} of {} and again on line {} of {}",
+            self.key, self.previous_line, self.previous_origin, self.line, self.origin
+        )
+    }
+}
+
+/// A record of every definition read by [`EnvLoader::load_with_report`](crate::EnvLoader::load_with_report).
+///
+/// # Examples
+///
+/// ```no_run
+/// use dotenvy::EnvLoader;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let report = EnvLoader::new().filename(".env").load_with_report()?;
+///
+/// if let Some(origin) = report.origin("DATABASE_URL") {
+///     println!("DATABASE_URL was loaded from {}", origin);
+/// }
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    pub(crate) definitions: Vec<Definition>,
+    pub(crate) unresolved: Vec<(Origin, Unresolved)>,
+    pub(crate) errors: Vec<(Origin, Error)>,
+    pub(crate) duplicates: Vec<Duplicate>,
+}
+
+static ENV_ORIGIN: Origin = Origin::Env;
+
+impl LoadReport {
+    /// Returns where the value of `key` in the environment came from.
+    ///
+    /// This is [`Origin::Env`] if an existing environment variable was preserved,
+    /// and `None` if `key` was not defined by any source.
+    pub fn origin(&self, key: &str) -> Option<&Origin> {
+        self.get(key).map(|definition| match definition.status {
+            Status::Skipped => &ENV_ORIGIN,
+            _ => &definition.origin,
+        })
+    }
+
+    /// Returns the definition of `key` that won over the others, the one that
+    /// was loaded unless the existing environment variable was preserved.
+    pub fn get(&self, key: &str) -> Option<&Definition> {
+        self.definitions
+            .iter()
+            .rev()
+            .find(|definition| definition.key == key && definition.status != Status::Superseded)
+    }
+
+    /// Returns every definition of `key`, in the order they were read.
+    pub fn definitions<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a Definition> {
+        self.definitions
+            .iter()
+            .filter(move |definition| definition.key == key)
+    }
+
+    /// Returns every definition, in the order they were read.
+    pub fn iter(&self) -> impl Iterator<Item = &Definition> {
+        self.definitions.iter()
+    }
+
+    /// Returns every reference to an undefined variable, along with the source
+    /// it was found in.
+    ///
+    /// This is only recorded if the loader was configured with
+    /// [`UndefinedVars::Collect`](crate::UndefinedVars::Collect).
+    pub fn unresolved(&self) -> impl Iterator<Item = (&Origin, &Unresolved)> {
+        self.unresolved
+            .iter()
+            .map(|(origin, unresolved)| (origin, unresolved))
+    }
+
+    /// Returns every line that could not be parsed or substituted, along with
+    /// the source it was found in.
+    ///
+    /// This is only recorded if the loader was configured with
+    /// [`lenient`](crate::EnvLoader::lenient), and is empty otherwise.
+    pub fn errors(&self) -> impl Iterator<Item = (&Origin, &Error)> {
+        self.errors.iter().map(|(origin, err)| (origin, err))
+    }
+
+    /// Returns every variable defined more than once, pointing at each
+    /// definition and the one before it.
+    ///
+    /// This is only recorded if the loader was configured with
+    /// [`DuplicateKeys::Warn`](crate::DuplicateKeys::Warn).
+    pub fn duplicates(&self) -> &[Duplicate] {
+        &self.duplicates
+    }
+}
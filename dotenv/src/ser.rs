@@ -0,0 +1,225 @@
+use std::io::{self, Write};
+
+use crate::document::Quote;
+use crate::errors::*;
+use crate::parse::KeyPolicy;
+
+/// Writes variables as *.env* text that parses back to the same values.
+///
+/// Each variable is written on its own line, in order. Values are left
+/// unquoted if that is safe, and put in single quotes, or else double quotes
+/// with escapes, if not, so that [`Iter`](crate::Iter) reads back every value
+/// exactly, including `$`, `#`, quotes and surrounding whitespace. Values
+/// holding control characters such as newlines are double quoted, with the
+/// control characters escaped.
+///
+/// Fails if a key is not accepted by [`KeyPolicy::Default`], so that the
+/// output can be read with the default settings, or if a value contains a NUL
+/// character, which environment variables cannot hold.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), dotenvy::Error> {
+/// let mut map = dotenvy::EnvMap::new();
+/// map.insert("HOST", "localhost");
+/// map.insert("GREETING", " it's $5 ");
+/// map.insert("MOTD", "line one\nline \"two\"");
+///
+/// let text = dotenvy::to_string(&map)?;
+/// assert_eq!(
+///     text,
+///     "HOST=localhost\nGREETING=\" it's \\$5 \"\nMOTD=\"line one\\nline \\\"two\\\"\"\n"
+/// );
+/// assert_eq!(dotenvy::from_read_map(text.as_bytes())?, map);
+/// #     Ok(())
+/// # }
+/// ```
+pub fn to_string<I, K, V>(vars: I) -> Result<String>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let mut text = Vec::new();
+    to_writer(&mut text, vars)?;
+    // only strings were written
+    Ok(String::from_utf8(text).unwrap_or_default())
+}
+
+/// Writes variables to `writer` as with [`to_string`].
+///
+/// Fails if a key or value cannot be written, in which case the variables
+/// before it are already written.
+pub fn to_writer<W, I, K, V>(mut writer: W, vars: I) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    for (key, value) in vars {
+        let key = key.as_ref();
+        let value = value.as_ref();
+        check_key(key, KeyPolicy::Default)?;
+        check_value(value)?;
+        writeln!(writer, "{}={}", key, quote(value, Quote::Unquoted)).map_err(Error::Io)?;
+    }
+    Ok(())
+}
+
+/// Writes `value` so that it is read back literally, keeping `preferred` quotes
+/// if they can hold it.
+pub(crate) fn quote(value: &str, preferred: Quote) -> String {
+    // control characters are only written as escapes
+    let control = value.chars().any(char::is_control);
+    let fits = |quote| match quote {
+        Quote::Double => true,
+        _ if control => false,
+        Quote::Unquoted => {
+            !value.contains("<<")
+                && value.chars().all(|c| {
+                    !c.is_whitespace() && !matches!(c, '\'' | '"' | '`' | '\\' | '$' | '#')
+                })
+        }
+        // a backslash before the closing quote would hide it when splitting lines
+        Quote::Single => !value.contains('\'') && !value.ends_with('\\'),
+        Quote::Backtick => !value.contains('`'),
+        Quote::Heredoc => false,
+    };
+    let quote = [preferred, Quote::Unquoted, Quote::Single]
+        .iter()
+        .copied()
+        .find(|&quote| fits(quote))
+        .unwrap_or(Quote::Double);
+
+    match quote {
+        Quote::Single => format!("'{}'", value),
+        Quote::Backtick => format!("`{}`", value),
+        Quote::Double => {
+            let mut quoted = String::from("\"");
+            for c in value.chars() {
+                match c {
+                    '\\' | '"' | '$' => {
+                        quoted.push('\\');
+                        quoted.push(c);
+                    }
+                    '\n' => quoted.push_str("\\n"),
+                    '\r' => quoted.push_str("\\r"),
+                    '\t' => quoted.push_str("\\t"),
+                    c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                    c => quoted.push(c),
+                }
+            }
+            quoted.push('"');
+            quoted
+        }
+        Quote::Unquoted | Quote::Heredoc => value.to_owned(),
+    }
+}
+
+/// Checks that `key` can be written, being accepted by `key_policy`.
+pub(crate) fn check_key(key: &str, key_policy: KeyPolicy) -> Result<()> {
+    if key_policy.is_valid(key) {
+        Ok(())
+    } else {
+        Err(invalid_key(key, key_policy))
+    }
+}
+
+pub(crate) fn invalid_key(key: &str, key_policy: KeyPolicy) -> Error {
+    let kind = ParseErrorKind::InvalidKey(InvalidKeyError::new(key.to_owned(), key_policy));
+    let mut err = ParseError::new(kind, 0);
+    err.locate(key, 1);
+    Error::Parse(err)
+}
+
+/// Checks that `value` can be written, not containing a NUL character.
+pub(crate) fn check_value(value: &str) -> Result<()> {
+    if value.contains('\0') {
+        Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "values cannot contain NUL characters",
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{EnvMap, Iter};
+
+    #[test]
+    fn test_roundtrip() {
+        let values = [
+            "",
+            "plain",
+            " leading",
+            "trailing ",
+            "\ttabs\t",
+            "multi\nline\r\nvalue\n",
+            "$HOME ${USER} $",
+            "#not a comment",
+            "value # comment",
+            "single ' quote",
+            "double \" quote",
+            "`backtick`",
+            "back\\slash\\",
+            "mixed '\"`\\$#\n",
+            "<<EOF",
+            "a=<<EOF",
+            "bell\x07 \u{e9}\u{85}\x7f",
+            "caf\u{e9} \u{1F600}",
+            "=",
+        ];
+        let map: EnvMap = values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| (format!("KEY_{}", index), *value))
+            .collect();
+
+        let text = to_string(&map).unwrap();
+        let parsed = Iter::new(text.as_bytes()).into_map().unwrap();
+        assert_eq!(parsed, map, "{}", text);
+    }
+
+    #[test]
+    fn test_quoting() {
+        let text = to_string(vec![
+            ("A", "plain"),
+            ("B", "two words"),
+            ("C", "it's"),
+            ("D", ""),
+        ])
+        .unwrap();
+        assert_eq!(text, "A=plain\nB='two words'\nC=\"it's\"\nD=\n");
+
+        let text = to_string(vec![("A", "tab\there"), ("B", "l1\nl2"), ("C", "\x07")]).unwrap();
+        assert_eq!(text, "A=\"tab\\there\"\nB=\"l1\\nl2\"\nC=\"\\u{7}\"\n");
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        let mut output = Vec::new();
+        let result = to_writer(&mut output, vec![("A", "1"), ("B C", "2")]);
+        assert!(matches!(
+            result,
+            Err(Error::Parse(ref err)) if matches!(err.kind(), ParseErrorKind::InvalidKey(_))
+        ));
+        assert_eq!(output, b"A=1\n");
+
+        for key in ["1A", "A-B", "caf\u{e9}"] {
+            assert!(to_string(vec![(key, "1")]).is_err(), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_nul_value() {
+        let result = to_string(vec![("A", "a\0b")]);
+        assert!(
+            matches!(result, Err(Error::Io(ref err)) if err.kind() == io::ErrorKind::InvalidInput)
+        );
+    }
+}
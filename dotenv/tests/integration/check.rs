@@ -0,0 +1,90 @@
+use std::fs;
+
+use dotenvy::Error;
+
+use crate::util::*;
+
+fn testenv(dotenv: &str, example: &str) -> TestEnv {
+    let testenv = TestEnv::init_with_envfile(dotenv);
+    fs::write(testenv.temp_path().join(".env.example"), example).unwrap();
+    testenv
+}
+
+#[test]
+fn check_against_matching() {
+    test_in_env(testenv("A=1\nB=2", "A=\nB=example"), || {
+        dotenvy::check_against(".env.example").expect("matches");
+    });
+}
+
+#[test]
+fn check_against_reports_diff() {
+    test_in_env(
+        testenv("A=1\nC=\nD=4", "A=\nB=\nC="),
+        || match dotenvy::check_against(".env.example") {
+            Err(Error::Check(diff)) => {
+                assert_eq!(diff.missing(), ["B"]);
+                assert_eq!(diff.empty(), ["C"]);
+                assert_eq!(diff.undeclared(), ["D"]);
+            }
+            result => panic!("expected a check error, got: {:?}", result),
+        },
+    );
+}
+
+#[test]
+fn check_against_does_not_substitute_reference() {
+    test_in_env(
+        testenv("A=1", "A=${A:?required}\nB=$A"),
+        || match dotenvy::check_against(".env.example") {
+            Err(Error::Check(diff)) => assert_eq!(diff.missing(), ["B"]),
+            result => panic!("expected a check error, got: {:?}", result),
+        },
+    );
+}
+
+#[test]
+fn check_against_uses_process_env() {
+    let mut testenv = testenv("A=1", "A=\nB=");
+    testenv.add_env_var("B", "from_env");
+
+    test_in_env(testenv, || {
+        dotenvy::check_against(".env.example").expect("matches");
+        assert_env_var_unset("A");
+    });
+}
+
+#[test]
+fn check_against_without_dotenv() {
+    let testenv = TestEnv::init();
+    fs::write(testenv.temp_path().join(".env.example"), "A=").unwrap();
+
+    test_in_env(testenv, || {
+        assert!(matches!(
+            dotenvy::check_against(".env.example"),
+            Err(Error::Check(_))
+        ));
+    });
+}
+
+#[test]
+fn check_against_missing_reference() {
+    test_in_env(TestEnv::init(), || {
+        assert!(dotenvy::check_against(".env.example")
+            .unwrap_err()
+            .not_found());
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn check_against_non_unicode_process_env() {
+    use std::env;
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    test_in_env(testenv("", "A="), || {
+        env::set_var("A", OsString::from_vec(b"\xff".to_vec()));
+        dotenvy::check_against(".env.example").expect("matches");
+    });
+}
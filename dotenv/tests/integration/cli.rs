@@ -0,0 +1,59 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Command, Output};
+
+use tempfile::{tempdir, TempDir};
+
+/// Creates a directory with a `.env` file and a `fmt` script printing its
+/// arguments and the value of `A`.
+fn setup() -> TempDir {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join(".env"), "A=1\n").unwrap();
+    fs::write(
+        dir.path().join("fmt"),
+        "#!/bin/sh\necho external $A \"$@\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(dir.path().join("fmt"), fs::Permissions::from_mode(0o755)).unwrap();
+    dir
+}
+
+fn dotenvy(dir: &TempDir, args: &[&str]) -> Output {
+    let path = env::var_os("PATH").unwrap_or_default();
+    let mut paths = vec![dir.path().to_owned()];
+    paths.extend(env::split_paths(&path));
+    Command::new(env!("CARGO_BIN_EXE_dotenvy"))
+        .args(args)
+        .current_dir(dir.path())
+        .env("PATH", env::join_paths(paths).unwrap())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn cli_runs_command_after_double_dash() {
+    let dir = setup();
+
+    let output = dotenvy(&dir, &["--", "fmt", "--check"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"external 1 --check\n");
+
+    let output = dotenvy(&dir, &["-f", ".env", "--", "fmt"]);
+    assert_eq!(output.stdout, b"external 1\n");
+}
+
+#[test]
+fn cli_subcommand_hides_program() {
+    let dir = setup();
+
+    let output = dotenvy(&dir, &["fmt", "--check"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn cli_requires_command_after_double_dash() {
+    let output = dotenvy(&setup(), &["--"]);
+    assert!(!output.status.success());
+}
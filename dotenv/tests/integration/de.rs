@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use dotenvy::{EnvDeserializer, EnvMap, Error};
+use serde::Deserialize;
+
+use crate::util::*;
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Config {
+    port: u16,
+    debug: bool,
+    hosts: Vec<String>,
+    mode: Mode,
+    timeout: Option<u32>,
+    retries: Option<u32>,
+    db: Db,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Db {
+    url: String,
+    pool: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    Dev,
+    Prod,
+}
+
+fn map(text: &str) -> EnvMap {
+    dotenvy::from_read_map(text.as_bytes()).expect("parse")
+}
+
+fn deserialize_error(err: Error) -> dotenvy::DeserializeError {
+    match err {
+        Error::Deserialize(err) => err,
+        err => panic!("expected a deserialize error, got: {}", err),
+    }
+}
+
+const CONFIG: &str = r#"
+APP_PORT=8080
+APP_DEBUG=yes
+APP_HOSTS="a.com, b.com"
+APP_MODE=prod
+APP_TIMEOUT=
+APP_DB__URL=postgres://localhost
+APP_DB__POOL=5
+OTHER=ignored
+"#;
+
+#[test]
+fn deserialize_prefixed_nested() {
+    let config: Config = EnvDeserializer::new()
+        .prefix("APP_")
+        .from_map(&map(CONFIG))
+        .expect("deserialize");
+
+    assert_eq!(
+        config,
+        Config {
+            port: 8080,
+            debug: true,
+            hosts: vec!["a.com".into(), "b.com".into()],
+            mode: Mode::Prod,
+            timeout: None,
+            retries: None,
+            db: Db {
+                url: "postgres://localhost".into(),
+                pool: Some(5),
+            },
+        }
+    );
+}
+
+#[test]
+fn deserialize_custom_separators() {
+    let map = map("DB_URL=x\nDB_POOL=1\nLIST=1;2;3");
+
+    let db: Db = EnvDeserializer::new()
+        .prefix("DB_")
+        .from_map(&map)
+        .expect("deserialize");
+    assert_eq!(db.pool, Some(1));
+
+    #[derive(Deserialize)]
+    struct Lists {
+        list: Vec<u8>,
+    }
+    let lists: Lists = EnvDeserializer::new()
+        .list_separator(';')
+        .from_vars(map.iter().filter(|(key, _)| *key == "LIST"))
+        .expect("deserialize");
+    assert_eq!(lists.list, vec![1, 2, 3]);
+}
+
+#[test]
+fn deserialize_reports_invalid_key() {
+    let err = EnvDeserializer::new()
+        .prefix("APP_")
+        .from_map::<Config>(&map(&CONFIG.replace("POOL=5", "POOL=many")))
+        .unwrap_err();
+
+    let err = deserialize_error(err);
+    assert_eq!(err.key(), Some("APP_DB__POOL"));
+    assert!(!err.missing());
+}
+
+#[test]
+fn deserialize_reports_missing_key() {
+    let err = EnvDeserializer::new()
+        .prefix("APP_")
+        .from_map::<Config>(&map(&CONFIG.replace("APP_DB__URL", "APP_DB__URI")))
+        .unwrap_err();
+
+    let err = deserialize_error(err);
+    assert_eq!(err.key(), Some("APP_DB__URL"));
+    assert!(err.missing());
+    assert_eq!(
+        err.to_string(),
+        "missing environment variable `APP_DB__URL`"
+    );
+}
+
+#[test]
+fn deserialize_from_env() {
+    let mut testenv = TestEnv::init();
+    testenv.set_env_vars_tuple(&[("SVC_NAME", "api"), ("SVC_WORKERS", "4")]);
+
+    test_in_env(testenv, || {
+        #[derive(Deserialize)]
+        struct Service {
+            name: String,
+            workers: usize,
+        }
+        let service: Service = dotenvy::from_env_prefixed("SVC_").expect("deserialize");
+        assert_eq!(service.name, "api");
+        assert_eq!(service.workers, 4);
+
+        let all: HashMap<String, String> = dotenvy::from_env().expect("deserialize");
+        assert_eq!(all.get("svc_name").map(String::as_str), Some("api"));
+    });
+}
+
+#[test]
+fn deserialize_renamed_fields() {
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    struct Settings {
+        #[serde(rename = "DATABASE_URL")]
+        url: String,
+        max_conns: u32,
+        #[serde(rename = "logLevel")]
+        log_level: String,
+    }
+
+    let settings: Settings = dotenvy::from_map(&map(
+        "DATABASE_URL=postgres://localhost\nMAX_CONNS=5\nloglevel=info",
+    ))
+    .expect("deserialize");
+    assert_eq!(settings.url, "postgres://localhost");
+    assert_eq!(settings.max_conns, 5);
+    assert_eq!(settings.log_level, "info");
+
+    let err = dotenvy::from_map::<Settings>(&map("MAX_CONNS=5\nLOGLEVEL=info")).unwrap_err();
+    assert_eq!(deserialize_error(err).key(), Some("DATABASE_URL"));
+}
+
+#[test]
+fn deserialize_reports_keys_differing_in_case() {
+    #[derive(Debug, Deserialize)]
+    struct Server {
+        port: u16,
+    }
+
+    let err = dotenvy::from_map::<Server>(&map("PORT=80\nport=8080")).unwrap_err();
+    let err = deserialize_error(err);
+    assert_eq!(err.key(), Some("port"));
+    assert!(!err.missing());
+    assert_eq!(
+        err.to_string(),
+        "environment variable `port`: conflicts with `PORT`, which differs only in case"
+    );
+
+    // variables that are not read may differ only in case
+    let server: Server =
+        dotenvy::from_map(&map("PORT=80\nhttp_proxy=a\nHTTP_PROXY=b")).expect("deserialize");
+    assert_eq!(server.port, 80);
+}
@@ -0,0 +1,339 @@
+use std::env;
+use std::fs;
+
+use dotenvy::{
+    DuplicateKeys, EnvLoader, EnvSource, Error, KeyPolicy, Origin, ParseErrorKind, Precedence,
+    UndefinedVars,
+};
+
+use crate::util::*;
+
+#[test]
+fn later_sources_win() {
+    let testenv = TestEnv::init_with_envfile("A=env\nB=env");
+    fs::write(testenv.temp_path().join(".env.local"), "B=local\nC=local").unwrap();
+
+    test_in_env(testenv, || {
+        EnvLoader::new()
+            .filename(".env")
+            .filename(".env.local")
+            .load()
+            .expect("load");
+
+        assert_env_var("A", "env");
+        assert_env_var("B", "local");
+        assert_env_var("C", "local");
+    });
+}
+
+#[test]
+fn env_precedence_preserves_existing() {
+    test_in_default_env(|| {
+        EnvLoader::new().filename(".env").load().expect("load");
+
+        assert_env_var(TEST_KEY, TEST_VALUE);
+        assert_env_var(TEST_EXISTING_KEY, TEST_EXISTING_VALUE);
+    });
+}
+
+#[test]
+fn file_precedence_overrides_existing() {
+    test_in_default_env(|| {
+        EnvLoader::new()
+            .filename(".env")
+            .precedence(Precedence::File)
+            .load()
+            .expect("load");
+
+        assert_env_var(TEST_KEY, TEST_VALUE);
+        assert_env_var(TEST_EXISTING_KEY, TEST_OVERRIDING_VALUE);
+    });
+}
+
+#[test]
+fn missing_required_source_fails() {
+    test_in_env(TestEnv::init(), || {
+        let err = EnvLoader::new()
+            .string("A=1")
+            .path("missing.env")
+            .load()
+            .expect_err("missing file");
+
+        assert!(err.not_found());
+        assert_env_var_unset("A");
+    });
+}
+
+#[test]
+fn missing_optional_source_is_skipped() {
+    test_in_default_env(|| {
+        EnvLoader::new()
+            .filename(".env")
+            .source(EnvSource::path("missing.env").optional())
+            .load()
+            .expect("load");
+
+        assert_env_var(TEST_KEY, TEST_VALUE);
+    });
+}
+
+#[test]
+fn substitution_across_sources() {
+    test_in_env(TestEnv::init(), || {
+        EnvLoader::new()
+            .string("HOST=localhost")
+            .reader("URL=http://${HOST}:8080".as_bytes())
+            .load()
+            .expect("load");
+
+        assert_env_var("URL", "http://localhost:8080");
+    });
+}
+
+#[test]
+fn invalid_source_loads_nothing() {
+    test_in_env(TestEnv::init(), || {
+        let result = EnvLoader::new()
+            .string("A=1")
+            .string(create_invalid_envfile())
+            .load();
+
+        assert!(result.is_err());
+        assert_env_var_unset("A");
+    });
+}
+
+#[test]
+fn layered_precedence() {
+    let testenv = TestEnv::init_with_envfile("A=env\nB=env\nC=env\nD=env");
+    let dir = testenv.temp_path().to_owned();
+    fs::write(dir.join(".env.local"), "B=local\nC=local\nD=local").unwrap();
+    fs::write(dir.join(".env.test"), "C=test\nD=test").unwrap();
+    fs::write(dir.join(".env.test.local"), "D=test_local").unwrap();
+
+    test_in_env(testenv, || {
+        EnvLoader::new()
+            .layered(&dir, Some("test"))
+            .load()
+            .expect("load");
+
+        assert_env_var("A", "env");
+        assert_env_var("B", "local");
+        assert_env_var("C", "test");
+        assert_env_var("D", "test_local");
+    });
+}
+
+#[test]
+fn layered_without_profile() {
+    let testenv = TestEnv::init_with_envfile("A=env");
+    let dir = testenv.temp_path().to_owned();
+    fs::write(dir.join(".env.test"), "A=test").unwrap();
+
+    test_in_env(testenv, || {
+        EnvLoader::new().layered(&dir, None).load().expect("load");
+
+        assert_env_var("A", "env");
+    });
+}
+
+#[test]
+fn layered_missing_files_are_optional() {
+    let testenv = TestEnv::init();
+    let dir = testenv.temp_path().to_owned();
+    fs::write(dir.join(".env.test.local"), "A=test_local").unwrap();
+
+    test_in_env(testenv, || {
+        EnvLoader::new()
+            .layered(&dir, Some("test"))
+            .load()
+            .expect("load");
+
+        assert_env_var("A", "test_local");
+    });
+}
+
+#[test]
+fn layered_profile_from_var() {
+    let mut testenv = TestEnv::init_with_envfile("A=env");
+    testenv.add_env_var("APP_ENV", "test");
+    let dir = testenv.temp_path().to_owned();
+    fs::write(dir.join(".env.test"), "A=test").unwrap();
+
+    test_in_env(testenv, || {
+        EnvLoader::new()
+            .layered_from_var(&dir, "APP_ENV")
+            .load()
+            .expect("load");
+
+        assert_env_var("A", "test");
+    });
+}
+
+#[test]
+fn undefined_vars_error_loads_nothing() {
+    let testenv = TestEnv::init();
+
+    test_in_env(testenv, || {
+        let err = EnvLoader::new()
+            .string("A=1\nPASSWORD=${DB_PASWORD}")
+            .undefined_vars(UndefinedVars::Error)
+            .load()
+            .unwrap_err();
+
+        match err {
+            Error::Substitution(err) => {
+                assert_eq!(err.var(), "DB_PASWORD");
+                assert_eq!(err.key(), "PASSWORD");
+                assert_eq!(err.line(), 2);
+            }
+            err => panic!("unexpected error {}", err),
+        }
+        assert_env_var_unset("A");
+    });
+}
+
+#[test]
+fn file_precedence_applies_to_substitution() {
+    let testenv = TestEnv::init();
+
+    test_in_env(testenv, || {
+        env::set_var("SUB_HOST", "env");
+        EnvLoader::new()
+            .string("SUB_HOST=file")
+            .string("SUB_URL=http://${SUB_HOST}")
+            .precedence(Precedence::File)
+            .load()
+            .expect("load");
+
+        assert_env_var("SUB_HOST", "file");
+        assert_env_var("SUB_URL", "http://file");
+    });
+}
+
+#[test]
+fn override_functions_substitute_file_values() {
+    let testenv = TestEnv::init();
+
+    test_in_env(testenv, || {
+        env::set_var("SUB_USER", "env");
+        dotenvy::from_read_override("SUB_USER=file\nSUB_HOME=/home/${SUB_USER}".as_bytes())
+            .expect("load");
+
+        assert_env_var("SUB_USER", "file");
+        assert_env_var("SUB_HOME", "/home/file");
+    });
+}
+
+#[test]
+fn forward_references_within_source() {
+    let testenv = TestEnv::init();
+
+    test_in_env(testenv, || {
+        EnvLoader::new()
+            .string("FWD_USER=admin")
+            .string("FWD_URL=postgres://${FWD_USER}@${FWD_HOST}\nFWD_HOST=db")
+            .forward_references()
+            .load()
+            .expect("load");
+
+        assert_env_var("FWD_URL", "postgres://admin@db");
+    });
+}
+
+#[test]
+fn resolver_replaces_env_in_substitution() {
+    let testenv = TestEnv::init();
+
+    test_in_env(testenv, || {
+        env::set_var("RES_REGION", "env");
+        let remote = dotenvy::from_read_map("RES_REGION=eu-west-1".as_bytes()).expect("map");
+
+        let map = EnvLoader::new()
+            .string("RES_BUCKET=assets-${RES_REGION}\nRES_PRICE='$5'\nRES_TOTAL=$10")
+            .resolver(&remote)
+            .into_map()
+            .expect("map");
+        assert_eq!(map.get("RES_BUCKET"), Some("assets-eu-west-1"));
+
+        let map = EnvLoader::new()
+            .string("RES_TOTAL=$10")
+            .no_substitution()
+            .into_map()
+            .expect("map");
+        assert_eq!(map.get("RES_TOTAL"), Some("$10"));
+    });
+}
+
+#[test]
+fn key_policy_applies_to_every_source() {
+    let testenv = TestEnv::init();
+
+    test_in_env(testenv, || {
+        let result = EnvLoader::new()
+            .string("KP_HOST=localhost")
+            .string("kp.port=8080")
+            .key_policy(KeyPolicy::Posix)
+            .load();
+        match result {
+            Err(Error::Parse(err)) => {
+                assert_eq!(err.source_name(), Some("<reader>"));
+                assert_eq!(err.line(), 1);
+                assert!(matches!(err.kind(), ParseErrorKind::InvalidKey(_)));
+            }
+            _ => panic!("expected an invalid key error"),
+        }
+        assert_env_var_unset("KP_HOST");
+    });
+}
+
+#[test]
+fn first_wins_keeps_earliest_definition() {
+    let testenv = TestEnv::init_with_envfile("DUP_A=file\nDUP_A=again\nDUP_B=${DUP_A}");
+    let path = testenv.envfile_path().to_owned();
+
+    test_in_env(testenv, || {
+        let map = EnvLoader::new()
+            .path(&path)
+            .string("DUP_A=later")
+            .duplicate_keys(DuplicateKeys::FirstWins)
+            .into_map()
+            .expect("map");
+        assert_eq!(map.get("DUP_A"), Some("file"));
+        assert_eq!(map.get("DUP_B"), Some("again"));
+
+        EnvLoader::new()
+            .path(&path)
+            .string("DUP_A=later")
+            .precedence(Precedence::File)
+            .duplicate_keys(DuplicateKeys::FirstWins)
+            .load()
+            .expect("load");
+        assert_env_var("DUP_A", "file");
+    });
+}
+
+#[test]
+fn duplicate_error_points_at_both_definitions() {
+    let testenv = TestEnv::init_with_envfile("DUPE_A=1\nDUPE_B=2");
+    let path = testenv.envfile_path().to_owned();
+
+    test_in_env(testenv, || {
+        let result = EnvLoader::new()
+            .path(&path)
+            .string("# local\nDUPE_B=3")
+            .duplicate_keys(DuplicateKeys::Error)
+            .load();
+        match result {
+            Err(Error::DuplicateKey(duplicate)) => {
+                assert_eq!(duplicate.key(), "DUPE_B");
+                assert_eq!(duplicate.previous_origin(), &Origin::Path(path.clone()));
+                assert_eq!(duplicate.previous_line(), 2);
+                assert_eq!(duplicate.origin(), &Origin::Reader("<reader>".to_string()));
+                assert_eq!(duplicate.line(), 2);
+            }
+            _ => panic!("expected a duplicate key error"),
+        }
+        assert_env_var_unset("DUPE_A");
+    });
+}
@@ -1 +1,11 @@
+mod check;
+// the binary execs commands with a Unix only API
+#[cfg(all(unix, feature = "cli"))]
+mod cli;
+#[cfg(feature = "serde")]
+mod de;
+mod loader;
+mod map;
+mod report;
 mod util;
+mod var;
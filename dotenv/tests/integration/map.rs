@@ -0,0 +1,77 @@
+use dotenvy::{EnvLoader, Precedence};
+
+use crate::util::*;
+
+#[test]
+fn map_does_not_modify_env() {
+    test_in_default_env(|| {
+        let map = dotenvy::dotenv_map().expect("parse");
+
+        assert_eq!(map.get(TEST_KEY), Some(TEST_VALUE));
+        assert_eq!(map.get(TEST_EXISTING_KEY), Some(TEST_OVERRIDING_VALUE));
+        assert_env_var_unset(TEST_KEY);
+        assert_env_var(TEST_EXISTING_KEY, TEST_EXISTING_VALUE);
+    });
+}
+
+#[test]
+fn map_keeps_last_duplicate() {
+    test_in_env(TestEnv::init_with_envfile("A=1\nB=2\nA=3"), || {
+        let map = dotenvy::from_filename_map(".env").expect("parse");
+
+        let entries: Vec<_> = map.iter().collect();
+        assert_eq!(entries, vec![("A", "3"), ("B", "2")]);
+    });
+}
+
+#[test]
+fn map_load_preserves_existing() {
+    test_in_default_env(|| {
+        dotenvy::dotenv_map().expect("parse").load();
+
+        assert_env_var(TEST_KEY, TEST_VALUE);
+        assert_env_var(TEST_EXISTING_KEY, TEST_EXISTING_VALUE);
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn map_load_preserves_existing_non_unicode() {
+    use std::env;
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    test_in_env(TestEnv::init_with_envfile("A=1"), || {
+        let existing = OsString::from_vec(b"\xff".to_vec());
+        env::set_var("A", &existing);
+        dotenvy::dotenv_map().expect("parse").load();
+
+        assert_eq!(env::var_os("A"), Some(existing));
+    });
+}
+
+#[test]
+fn map_load_override() {
+    test_in_default_env(|| {
+        dotenvy::dotenv_map().expect("parse").load_override();
+
+        assert_env_var(TEST_KEY, TEST_VALUE);
+        assert_env_var(TEST_EXISTING_KEY, TEST_OVERRIDING_VALUE);
+    });
+}
+
+#[test]
+fn loader_into_map_ignores_precedence() {
+    test_in_default_env(|| {
+        let map = EnvLoader::new()
+            .filename(".env")
+            .string("EXTRA=1")
+            .precedence(Precedence::Env)
+            .into_map()
+            .expect("parse");
+
+        assert_eq!(map.get(TEST_EXISTING_KEY), Some(TEST_OVERRIDING_VALUE));
+        assert_eq!(map.get("EXTRA"), Some("1"));
+        assert_env_var_unset("EXTRA");
+    });
+}
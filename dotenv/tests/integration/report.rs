@@ -0,0 +1,275 @@
+use std::fs;
+
+use dotenvy::{DuplicateKeys, EnvLoader, EnvSource, Origin, Precedence, Status, UndefinedVars};
+
+use crate::util::*;
+
+#[test]
+fn report_records_origin_and_line() {
+    let testenv = TestEnv::init_with_envfile("# comment\nA=1\nB=\"multi\nline\"\nC=3");
+    let path = testenv.envfile_path().to_owned();
+
+    test_in_env(testenv, || {
+        let report = EnvLoader::new()
+            .path(&path)
+            .load_with_report()
+            .expect("load");
+
+        assert_eq!(report.origin("A"), Some(&Origin::Path(path.clone())));
+        assert_eq!(report.get("A").unwrap().line(), 2);
+        assert_eq!(report.get("B").unwrap().line(), 3);
+        assert_eq!(report.get("C").unwrap().line(), 5);
+        assert_eq!(report.origin("MISSING"), None);
+    });
+}
+
+#[test]
+fn report_records_raw_value() {
+    let testenv = TestEnv::init();
+
+    test_in_env(testenv, || {
+        let report = EnvLoader::new()
+            .string("HOST=localhost\nURL=\"http://${HOST}\"   # the url")
+            .load_with_report()
+            .expect("load");
+
+        let url = report.get("URL").unwrap();
+        assert_eq!(url.raw(), "\"http://${HOST}\"");
+        assert_eq!(url.value(), "http://localhost");
+    });
+}
+
+#[test]
+fn report_skipped_existing() {
+    test_in_default_env(|| {
+        let report = EnvLoader::new()
+            .filename(".env")
+            .load_with_report()
+            .expect("load");
+
+        let existing = report.get(TEST_EXISTING_KEY).unwrap();
+        assert_eq!(existing.status(), Status::Skipped);
+        assert_eq!(existing.value(), TEST_OVERRIDING_VALUE);
+        assert_eq!(report.origin(TEST_EXISTING_KEY), Some(&Origin::Env));
+        assert_eq!(report.get(TEST_KEY).unwrap().status(), Status::Set);
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn report_skipped_existing_non_unicode() {
+    use std::env;
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    test_in_env(TestEnv::init_with_envfile("A=1"), || {
+        let existing = OsString::from_vec(b"\xff".to_vec());
+        env::set_var("A", &existing);
+        let report = EnvLoader::new()
+            .filename(".env")
+            .load_with_report()
+            .expect("load");
+
+        assert_eq!(report.get("A").unwrap().status(), Status::Skipped);
+        assert_eq!(env::var_os("A"), Some(existing));
+    });
+}
+
+#[test]
+fn report_overridden_existing() {
+    test_in_default_env(|| {
+        let report = EnvLoader::new()
+            .filename(".env")
+            .precedence(Precedence::File)
+            .load_with_report()
+            .expect("load");
+
+        let existing = report.get(TEST_EXISTING_KEY).unwrap();
+        assert_eq!(existing.status(), Status::Overridden);
+        assert!(matches!(
+            report.origin(TEST_EXISTING_KEY),
+            Some(Origin::Path(_))
+        ));
+    });
+}
+
+#[test]
+fn report_superseded_across_sources() {
+    let testenv = TestEnv::init_with_envfile("A=file");
+    let local = testenv.temp_path().join(".env.local");
+    fs::write(&local, "A=local").unwrap();
+
+    test_in_env(testenv, || {
+        let report = EnvLoader::new()
+            .filename(".env")
+            .source(EnvSource::path(&local))
+            .source(EnvSource::string("A=ipc").label("ipc"))
+            .load_with_report()
+            .expect("load");
+
+        let statuses: Vec<_> = report.definitions("A").map(|d| d.status()).collect();
+        assert_eq!(
+            statuses,
+            vec![Status::Superseded, Status::Superseded, Status::Set]
+        );
+        assert_eq!(report.origin("A"), Some(&Origin::Reader("ipc".into())));
+        assert_env_var("A", "ipc");
+    });
+}
+
+#[test]
+fn report_collects_unresolved() {
+    let testenv = TestEnv::init();
+
+    test_in_env(testenv, || {
+        let report = EnvLoader::new()
+            .source(EnvSource::string("A=1\nB=$A${UNDEFINED_B}").label("first"))
+            .source(EnvSource::string("C=${UNDEFINED_C}").label("second"))
+            .undefined_vars(UndefinedVars::Collect)
+            .load_with_report()
+            .expect("load");
+
+        assert_eq!(report.get("B").unwrap().value(), "1");
+        let unresolved: Vec<_> = report
+            .unresolved()
+            .map(|(origin, unresolved)| {
+                (
+                    origin.to_string(),
+                    unresolved.var().to_owned(),
+                    unresolved.key().to_owned(),
+                    unresolved.line(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            unresolved,
+            vec![
+                ("first".into(), "UNDEFINED_B".into(), "B".into(), 2),
+                ("second".into(), "UNDEFINED_C".into(), "C".into(), 1),
+            ]
+        );
+    });
+}
+
+#[test]
+fn report_lines_after_multi_line_values() {
+    let testenv = TestEnv::init();
+
+    test_in_env(testenv, || {
+        let report = EnvLoader::new()
+            .string("A=<<EOF\none\ntwo\nEOF\nB=first\\\n  second\nC=3")
+            .load_with_report()
+            .expect("load");
+
+        assert_eq!(report.get("A").unwrap().value(), "one\ntwo");
+        assert_eq!(report.get("B").unwrap().line(), 5);
+        assert_eq!(report.get("B").unwrap().value(), "firstsecond");
+        assert_eq!(report.get("C").unwrap().line(), 7);
+    });
+}
+
+#[test]
+fn report_collects_errors_when_lenient() {
+    let testenv = TestEnv::init_with_envfile("LEN_A=1\nLEN_B='open\nLEN_C=3\n");
+    let path = testenv.envfile_path().to_owned();
+
+    test_in_env(testenv, || {
+        let report = EnvLoader::new()
+            .path(&path)
+            .string("LEN_D 4\nLEN_E=5")
+            .lenient()
+            .load_with_report()
+            .expect("report");
+
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].0, &Origin::Path(path.clone()));
+        assert!(errors[0].1.to_string().contains(":2:7"));
+        assert_eq!(errors[1].0, &Origin::Reader("<reader>".to_string()));
+
+        assert_env_var("LEN_A", "1");
+        assert_env_var_unset("LEN_B");
+        assert_env_var("LEN_C", "3");
+        assert_env_var("LEN_E", "5");
+    });
+}
+
+#[test]
+fn report_warns_about_duplicates() {
+    let testenv = TestEnv::init_with_envfile("WARN_A=1\nWARN_B=2\nWARN_A=3\n");
+    let path = testenv.envfile_path().to_owned();
+
+    test_in_env(testenv, || {
+        let report = EnvLoader::new()
+            .path(&path)
+            .string("WARN_B=4")
+            .duplicate_keys(DuplicateKeys::Warn)
+            .load_with_report()
+            .expect("report");
+
+        let duplicates = report.duplicates();
+        assert_eq!(duplicates.len(), 2);
+        assert_eq!(
+            duplicates[0].to_string(),
+            format!(
+                "duplicate key `WARN_A`: defined on line 1 of {} and again on line 3 of {}",
+                path.display(),
+                path.display()
+            )
+        );
+        assert_eq!(duplicates[1].key(), "WARN_B");
+        assert_eq!(
+            (duplicates[1].previous_line(), duplicates[1].line()),
+            (2, 1)
+        );
+
+        assert_env_var("WARN_A", "3");
+        assert_env_var("WARN_B", "4");
+    });
+}
+
+#[test]
+fn report_first_wins_supersedes_later_definitions() {
+    let testenv = TestEnv::init();
+
+    test_in_env(testenv, || {
+        let report = EnvLoader::new()
+            .string("FIRST_A=1\nFIRST_A=2")
+            .string("FIRST_A=3")
+            .duplicate_keys(DuplicateKeys::FirstWins)
+            .load_with_report()
+            .expect("report");
+
+        let statuses: Vec<_> = report.definitions("FIRST_A").map(|d| d.status()).collect();
+        assert_eq!(
+            statuses,
+            vec![Status::Set, Status::Superseded, Status::Superseded]
+        );
+        assert_eq!(report.get("FIRST_A").unwrap().value(), "1");
+        assert!(report.duplicates().is_empty());
+        assert_env_var("FIRST_A", "1");
+    });
+}
+
+#[test]
+fn report_collects_duplicate_errors_when_lenient() {
+    let testenv = TestEnv::init();
+
+    test_in_env(testenv, || {
+        let report = EnvLoader::new()
+            .string("LDUP_A=1\nLDUP_A=2\nLDUP_B=3")
+            .duplicate_keys(DuplicateKeys::Error)
+            .lenient()
+            .load_with_report()
+            .expect("report");
+
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .1
+            .to_string()
+            .starts_with("duplicate key `LDUP_A`"));
+        assert_env_var("LDUP_A", "1");
+        assert_env_var("LDUP_B", "3");
+    });
+}
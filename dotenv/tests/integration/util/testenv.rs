@@ -24,7 +24,7 @@ static ENV_LOCKER: OnceCell<Arc<Mutex<EnvMap>>> = OnceCell::new();
 /// - [`TestEnv::init`]: blank environment (no envfile)
 /// - [`TestEnv::init_with_envfile`]: blank environment with an envfile
 /// - [`TestEnv::default`]: default testing environment (1 existing var and 2
-///       set in a `.env` file)
+///   set in a `.env` file)
 #[derive(Debug)]
 pub struct TestEnv {
     temp_dir: TempDir,
@@ -292,8 +292,11 @@ fn get_env_locker() -> Arc<Mutex<EnvMap>> {
 /// Reset the process' env vars back to what was in `original_env`.
 fn reset_env(original_env: &EnvMap) {
     // remove keys if they weren't in the original environment
-    env::vars()
-        .filter(|(key, _)| !original_env.contains_key(key))
+    env::vars_os()
+        .filter(|(key, _)| {
+            key.to_str()
+                .map_or(true, |key| !original_env.contains_key(key))
+        })
         .for_each(|(key, _)| env::remove_var(key));
     // ensure original keys have their original values
     original_env
@@ -0,0 +1,122 @@
+use std::error::Error as StdError;
+use std::num::ParseIntError;
+
+use dotenvy::{Error, ValueErrorKind};
+
+use crate::util::*;
+
+fn testenv(vars: &[(&str, &str)]) -> TestEnv {
+    let mut testenv = TestEnv::init();
+    testenv.set_env_vars_tuple(vars);
+    testenv
+}
+
+fn value_error_kind(err: Error) -> ValueErrorKind {
+    match err {
+        Error::Value(err) => err.kind(),
+        err => panic!("expected a value error, got: {}", err),
+    }
+}
+
+#[test]
+fn var_parse_typed() {
+    test_in_env(testenv(&[("PORT", "8080")]), || {
+        let port: u16 = dotenvy::var_parse("PORT").expect("parse");
+        assert_eq!(port, 8080);
+    });
+}
+
+#[test]
+fn var_parse_invalid() {
+    test_in_env(testenv(&[("PORT", "eighty")]), || {
+        let err = dotenvy::var_parse::<u16, _>("PORT").unwrap_err();
+
+        assert!(err.source().unwrap().is::<ParseIntError>());
+        match &err {
+            Error::Value(value_err) => {
+                assert_eq!(value_err.key(), "PORT");
+                assert_eq!(value_err.value(), Some("eighty"));
+                assert_eq!(value_err.kind(), ValueErrorKind::Invalid);
+            }
+            err => panic!("expected a value error, got: {}", err),
+        }
+        assert!(!err.redact().to_string().contains("eighty"));
+    });
+}
+
+#[test]
+fn var_parse_missing() {
+    test_in_env(testenv(&[]), || {
+        let err = dotenvy::var_parse::<u16, _>("PORT").unwrap_err();
+        assert_eq!(value_error_kind(err), ValueErrorKind::NotPresent);
+    });
+}
+
+#[test]
+fn var_opt_and_or() {
+    test_in_env(testenv(&[("WORKERS", "8"), ("BAD", "x")]), || {
+        assert_eq!(dotenvy::var_opt::<u8, _>("WORKERS").unwrap(), Some(8));
+        assert_eq!(dotenvy::var_opt::<u8, _>("MISSING").unwrap(), None);
+        assert_eq!(dotenvy::var_or("WORKERS", 4u8).unwrap(), 8);
+        assert_eq!(dotenvy::var_or("MISSING", 4u8).unwrap(), 4);
+        assert!(dotenvy::var_or("BAD", 4u8).is_err());
+    });
+}
+
+#[test]
+fn var_bool_values() {
+    let vars = [
+        ("A", "true"),
+        ("B", " Yes "),
+        ("C", "0"),
+        ("D", "OFF"),
+        ("E", "nope"),
+    ];
+    test_in_env(testenv(&vars), || {
+        assert!(dotenvy::var_bool("A").unwrap());
+        assert!(dotenvy::var_bool("B").unwrap());
+        assert!(!dotenvy::var_bool("C").unwrap());
+        assert!(!dotenvy::var_bool("D").unwrap());
+        let err = dotenvy::var_bool("E").unwrap_err();
+        assert_eq!(value_error_kind(err), ValueErrorKind::Invalid);
+    });
+}
+
+#[test]
+fn var_list_values() {
+    test_in_env(
+        testenv(&[("PORTS", "80, 443,,8080"), ("EMPTY", "")]),
+        || {
+            let ports: Vec<u16> = dotenvy::var_list("PORTS", ',').unwrap();
+            assert_eq!(ports, vec![80, 443, 8080]);
+            let empty: Vec<String> = dotenvy::var_list("EMPTY", ',').unwrap();
+            assert!(empty.is_empty());
+            assert!(dotenvy::var_list::<u8, _>("PORTS", ',').is_err());
+        },
+    );
+}
+
+#[test]
+fn var_os_and_vars_os() {
+    test_in_env(testenv(&[("DATA_DIR", "/srv/data")]), || {
+        assert_eq!(dotenvy::var_os("DATA_DIR").unwrap(), "/srv/data");
+        assert!(dotenvy::var_os("NO_SUCH_DIR").is_none());
+        assert!(dotenvy::vars_os().any(|(key, value)| key == "DATA_DIR" && value == "/srv/data"));
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn dotenv_preserves_existing_non_unicode() {
+    use std::env;
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    test_in_env(TestEnv::init_with_envfile("A=1"), || {
+        let existing = OsString::from_vec(b"\xff".to_vec());
+        env::set_var("A", &existing);
+        dotenvy::dotenv().expect("load");
+
+        assert_eq!(env::var_os("A"), Some(existing));
+    });
+}
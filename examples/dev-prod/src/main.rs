@@ -1,17 +1,25 @@
 use std::{
     env,
     fmt::{self},
+    path::Path,
 };
 
+use dotenvy::EnvLoader;
+
 #[derive(PartialEq)]
 enum AppEnv {
     Dev,
     Prod,
 }
 
-/// A common setup that:
-///  - loads from a .env file in dev mode
-///  - loads from the environment in prod mode
+/// A common setup that layers *.env* files by the profile in `APP_ENV`:
+///  - `.env` holds the defaults used in dev mode
+///  - `.env.prod` overrides them in prod mode
+///  - `.env.local` and `.env.prod.local` hold uncommitted overrides
+///
+/// The files are read from the `examples` directory, wherever this is run from.
+///
+/// Variables set in the environment always take precedence.
 ///
 /// A few commands to try:
 /// 1) `cargo run`
@@ -25,12 +33,11 @@ fn main() {
 
     println!("Running in {app_env} mode");
 
-    if app_env == AppEnv::Dev {
-        match dotenvy::dotenv() {
-            Ok(path) => println!(".env read successfully from {}", path.display()),
-            Err(e) => println!("Could not load .env file: {e}"),
-        };
-    }
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    match EnvLoader::new().layered_from_var(dir, "APP_ENV").load() {
+        Ok(()) => println!(".env files read successfully"),
+        Err(e) => println!("Could not load .env files: {e}"),
+    };
 
     let host = env::var("HOST").expect("HOST not set");
     println!("Host: {host}");